@@ -1,11 +1,25 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt};
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
     pub variables: Vec<String>,
+    #[serde(default)]
+    pub constants: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
     pub body: Node,
+    #[serde(default)]
+    pub handlers: Vec<Handler>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameter {
+    pub name: String,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
 }
 
 impl fmt::Display for Program {
@@ -21,18 +35,65 @@ impl fmt::Display for Program {
             writer.writeln(")");
         }
 
+        for (name, value) in &self.constants {
+            writer.writeln(&format!("Constant({}={})", name, value));
+        }
+
+        for parameter in &self.parameters {
+            writer.writeln(&format!(
+                "Parameter({}, default={}, min={}, max={})",
+                parameter.name, parameter.default, parameter.min, parameter.max
+            ));
+        }
+
         writer.writeln("");
 
         self.body.display(&mut writer);
 
+        for handler in &self.handlers {
+            writer.finish_line();
+            handler.display(&mut writer);
+        }
+
         write!(f, "{}", writer.finish())
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    Start,
+    Timer { period: u32 },
+    Input { channel: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handler {
+    pub event: Event,
+    pub body: Node,
+}
+
+impl AstDisplay for Handler {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        match self.event {
+            Event::Start => writer.writeln("OnStart"),
+            Event::Timer { period } => writer.writeln(&format!("OnTimer(period={})", period)),
+            Event::Input { channel } => writer.writeln(&format!("OnInput(channel={})", channel)),
+        }
+
+        writer.indent();
+        writer.writeln("");
+        self.body.display(writer);
+        writer.writeln("");
+        writer.dedent();
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "kebab-case")]
 pub enum Node {
     Sequence(Sequence),
+    Block(Block),
     Naked(Naked),
     Compare(Compare),
     Logic(Logic),
@@ -50,18 +111,53 @@ pub enum Node {
     Arithmetic(Arithmetic),
     Between(Between),
     Rand(Rand),
+    Seed(Seed),
+    #[serde(rename = "noise-1d")]
+    Noise1D(Noise1D),
+    #[serde(rename = "noise-2d")]
+    Noise2D(Noise2D),
     GetVariable(GetVariable),
     SetVariable(SetVariable),
+    GetConstant(GetConstant),
+    GetParameter(GetParameter),
     Len(Len),
+    PosX(PosX),
+    PosY(PosY),
+    Width(Width),
+    Height(Height),
     Get(Get),
     Set(Set),
+    Rgb(Rgb),
+    Hsv(Hsv),
+    Hsl(Hsl),
+    ColorChannel(ColorChannel),
+    GetColor(GetColor),
+    SetColor(SetColor),
+    #[serde(rename = "set-xy")]
+    SetXY(SetXY),
+    Blend(Blend),
+    Scale(Scale),
+    AddSat(AddSat),
+    PaletteLookup(PaletteLookup),
+    FadeAll(FadeAll),
+    Fill(Fill),
+    Gradient(Gradient),
+    Shift(Shift),
+    Rotate(Rotate),
+    Mirror(Mirror),
     Sleep(Sleep),
+    Input(Input),
+    Now(Now),
+    Elapsed(Elapsed),
+    WaitFrame(WaitFrame),
+    Spawn(Spawn),
 }
 
 impl AstDisplay for Node {
     fn display(&self, writer: &mut AstDisplayWriter) {
         match self {
             Node::Sequence(s) => s.display(writer),
+            Node::Block(b) => b.display(writer),
             Node::Naked(n) => n.display(writer),
             Node::Compare(c) => c.display(writer),
             Node::Logic(l) => l.display(writer),
@@ -79,12 +175,43 @@ impl AstDisplay for Node {
             Node::Arithmetic(a) => a.display(writer),
             Node::Between(b) => b.display(writer),
             Node::Rand(r) => r.display(writer),
+            Node::Seed(s) => s.display(writer),
+            Node::Noise1D(n) => n.display(writer),
+            Node::Noise2D(n) => n.display(writer),
             Node::GetVariable(g) => g.display(writer),
             Node::SetVariable(s) => s.display(writer),
+            Node::GetConstant(g) => g.display(writer),
+            Node::GetParameter(g) => g.display(writer),
             Node::Len(l) => l.display(writer),
+            Node::PosX(p) => p.display(writer),
+            Node::PosY(p) => p.display(writer),
+            Node::Width(w) => w.display(writer),
+            Node::Height(h) => h.display(writer),
             Node::Get(g) => g.display(writer),
             Node::Set(s) => s.display(writer),
+            Node::Rgb(r) => r.display(writer),
+            Node::Hsv(h) => h.display(writer),
+            Node::Hsl(h) => h.display(writer),
+            Node::ColorChannel(c) => c.display(writer),
+            Node::GetColor(g) => g.display(writer),
+            Node::SetColor(s) => s.display(writer),
+            Node::SetXY(s) => s.display(writer),
+            Node::Blend(b) => b.display(writer),
+            Node::Scale(s) => s.display(writer),
+            Node::AddSat(a) => a.display(writer),
+            Node::PaletteLookup(p) => p.display(writer),
+            Node::FadeAll(f) => f.display(writer),
+            Node::Fill(f) => f.display(writer),
+            Node::Gradient(g) => g.display(writer),
+            Node::Shift(s) => s.display(writer),
+            Node::Rotate(r) => r.display(writer),
+            Node::Mirror(m) => m.display(writer),
             Node::Sleep(s) => s.display(writer),
+            Node::Input(i) => i.display(writer),
+            Node::Now(n) => n.display(writer),
+            Node::Elapsed(e) => e.display(writer),
+            Node::WaitFrame(w) => w.display(writer),
+            Node::Spawn(s) => s.display(writer),
         }
     }
 }
@@ -103,6 +230,22 @@ impl AstDisplay for Sequence {
     }
 }
 
+// Statement coming from a block of the designer, used to map the code back to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub id: String,
+    pub body: Box<Node>,
+}
+
+impl AstDisplay for Block {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("[");
+        writer.write(&self.id);
+        writer.write("] ");
+        self.body.display(writer);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Naked {
     pub value: Box<Node>,
@@ -445,6 +588,55 @@ impl AstDisplay for Rand {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Seed {
+    pub seed: Box<Node>,
+}
+
+impl AstDisplay for Seed {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Seed(seed=");
+        self.seed.display(writer);
+        writer.write(")");
+    }
+}
+
+// coordinates have 8 fractional bits, result is 0-255
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Noise1D {
+    pub x: Box<Node>,
+    pub seed: Box<Node>,
+}
+
+impl AstDisplay for Noise1D {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Noise1D(x=");
+        self.x.display(writer);
+        writer.write(", seed=");
+        self.seed.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Noise2D {
+    pub x: Box<Node>,
+    pub y: Box<Node>,
+    pub seed: Box<Node>,
+}
+
+impl AstDisplay for Noise2D {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Noise2D(x=");
+        self.x.display(writer);
+        writer.write(", y=");
+        self.y.display(writer);
+        writer.write(", seed=");
+        self.seed.display(writer);
+        writer.write(")");
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetVariable {
     pub variable: String,
@@ -474,6 +666,32 @@ impl AstDisplay for SetVariable {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetConstant {
+    pub constant: String,
+}
+
+impl AstDisplay for GetConstant {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("GetConstant(constant=");
+        writer.write(&self.constant);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetParameter {
+    pub parameter: String,
+}
+
+impl AstDisplay for GetParameter {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("GetParameter(parameter=");
+        writer.write(&self.parameter);
+        writer.write(")");
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Len {}
 
@@ -483,9 +701,54 @@ impl AstDisplay for Len {
     }
 }
 
+// coordinates are relative to the top left corner of the layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosX {
+    pub index: Box<Node>,
+}
+
+impl AstDisplay for PosX {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("PosX(index=");
+        self.index.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PosY {
+    pub index: Box<Node>,
+}
+
+impl AstDisplay for PosY {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("PosY(index=");
+        self.index.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Width {}
+
+impl AstDisplay for Width {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Width()");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Height {}
+
+impl AstDisplay for Height {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Height()");
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
-pub enum GetColor {
+pub enum Channel {
     Red,
     Green,
     Blue,
@@ -494,7 +757,7 @@ pub enum GetColor {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Get {
     pub index: Box<Node>,
-    pub color: GetColor,
+    pub color: Channel,
 }
 
 impl AstDisplay for Get {
@@ -504,9 +767,9 @@ impl AstDisplay for Get {
         writer.write(", color=");
 
         match self.color {
-            GetColor::Red => writer.write("Red"),
-            GetColor::Green => writer.write("Green"),
-            GetColor::Blue => writer.write("Blue"),
+            Channel::Red => writer.write("Red"),
+            Channel::Green => writer.write("Green"),
+            Channel::Blue => writer.write("Blue"),
         }
 
         writer.write(")");
@@ -535,6 +798,299 @@ impl AstDisplay for Set {
     }
 }
 
+// Colors are packed in a single value: 0xRRGGBB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rgb {
+    pub red: Box<Node>,
+    pub green: Box<Node>,
+    pub blue: Box<Node>,
+}
+
+impl AstDisplay for Rgb {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Rgb(red=");
+        self.red.display(writer);
+        writer.write(", green=");
+        self.green.display(writer);
+        writer.write(", blue=");
+        self.blue.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hsv {
+    pub hue: Box<Node>,
+    pub saturation: Box<Node>,
+    pub value: Box<Node>,
+}
+
+impl AstDisplay for Hsv {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Hsv(hue=");
+        self.hue.display(writer);
+        writer.write(", saturation=");
+        self.saturation.display(writer);
+        writer.write(", value=");
+        self.value.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hsl {
+    pub hue: Box<Node>,
+    pub saturation: Box<Node>,
+    pub lightness: Box<Node>,
+}
+
+impl AstDisplay for Hsl {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Hsl(hue=");
+        self.hue.display(writer);
+        writer.write(", saturation=");
+        self.saturation.display(writer);
+        writer.write(", lightness=");
+        self.lightness.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColorChannel {
+    pub color: Box<Node>,
+    pub channel: Channel,
+}
+
+impl AstDisplay for ColorChannel {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("ColorChannel(color=");
+        self.color.display(writer);
+        writer.write(", channel=");
+
+        match self.channel {
+            Channel::Red => writer.write("Red"),
+            Channel::Green => writer.write("Green"),
+            Channel::Blue => writer.write("Blue"),
+        }
+
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetColor {
+    pub index: Box<Node>,
+}
+
+impl AstDisplay for GetColor {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("GetColor(index=");
+        self.index.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetColor {
+    pub index: Box<Node>,
+    pub color: Box<Node>,
+}
+
+impl AstDisplay for SetColor {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("SetColor(index=");
+        self.index.display(writer);
+        writer.write(", color=");
+        self.color.display(writer);
+        writer.write(")");
+    }
+}
+
+// only on matrix layouts, x is the column and y the row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetXY {
+    pub x: Box<Node>,
+    pub y: Box<Node>,
+    pub color: Box<Node>,
+}
+
+impl AstDisplay for SetXY {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("SetXY(x=");
+        self.x.display(writer);
+        writer.write(", y=");
+        self.y.display(writer);
+        writer.write(", color=");
+        self.color.display(writer);
+        writer.write(")");
+    }
+}
+
+// amounts are in 0-255
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blend {
+    pub color1: Box<Node>,
+    pub color2: Box<Node>,
+    pub amount: Box<Node>,
+}
+
+impl AstDisplay for Blend {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Blend(color1=");
+        self.color1.display(writer);
+        writer.write(", color2=");
+        self.color2.display(writer);
+        writer.write(", amount=");
+        self.amount.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scale {
+    pub color: Box<Node>,
+    pub amount: Box<Node>,
+}
+
+impl AstDisplay for Scale {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Scale(color=");
+        self.color.display(writer);
+        writer.write(", amount=");
+        self.amount.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddSat {
+    pub color1: Box<Node>,
+    pub color2: Box<Node>,
+}
+
+impl AstDisplay for AddSat {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("AddSat(color1=");
+        self.color1.display(writer);
+        writer.write(", color2=");
+        self.color2.display(writer);
+        writer.write(")");
+    }
+}
+
+// palette is the name of a built-in palette, position is in 0-255
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaletteLookup {
+    pub palette: String,
+    pub position: Box<Node>,
+}
+
+impl AstDisplay for PaletteLookup {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write(&format!("PaletteLookup(palette={}, position=", self.palette));
+        self.position.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FadeAll {
+    pub amount: Box<Node>,
+}
+
+impl AstDisplay for FadeAll {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("FadeAll(amount=");
+        self.amount.display(writer);
+        writer.write(")");
+    }
+}
+
+// strip operations, ranges include both ends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub start: Box<Node>,
+    pub end: Box<Node>,
+    pub color: Box<Node>,
+}
+
+impl AstDisplay for Fill {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Fill(start=");
+        self.start.display(writer);
+        writer.write(", end=");
+        self.end.display(writer);
+        writer.write(", color=");
+        self.color.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gradient {
+    pub start: Box<Node>,
+    pub end: Box<Node>,
+    pub color1: Box<Node>,
+    pub color2: Box<Node>,
+}
+
+impl AstDisplay for Gradient {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Gradient(start=");
+        self.start.display(writer);
+        writer.write(", end=");
+        self.end.display(writer);
+        writer.write(", color1=");
+        self.color1.display(writer);
+        writer.write(", color2=");
+        self.color2.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shift {
+    pub offset: Box<Node>,
+}
+
+impl AstDisplay for Shift {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Shift(offset=");
+        self.offset.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rotate {
+    pub offset: Box<Node>,
+}
+
+impl AstDisplay for Rotate {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Rotate(offset=");
+        self.offset.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mirror {
+    pub start: Box<Node>,
+    pub end: Box<Node>,
+}
+
+impl AstDisplay for Mirror {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Mirror(start=");
+        self.start.display(writer);
+        writer.write(", end=");
+        self.end.display(writer);
+        writer.write(")");
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sleep {
     pub delay: Box<Node>,
@@ -548,6 +1104,63 @@ impl AstDisplay for Sleep {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Input {
+    pub channel: Box<Node>,
+}
+
+impl AstDisplay for Input {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Input(channel=");
+        self.channel.display(writer);
+        writer.write(")");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Now {}
+
+impl AstDisplay for Now {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Now()");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Elapsed {}
+
+impl AstDisplay for Elapsed {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("Elapsed()");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaitFrame {}
+
+impl AstDisplay for WaitFrame {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.write("WaitFrame");
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spawn {
+    pub body: Box<Node>,
+}
+
+impl AstDisplay for Spawn {
+    fn display(&self, writer: &mut AstDisplayWriter) {
+        writer.writeln("Spawn");
+
+        writer.indent();
+        writer.writeln("");
+        self.body.display(writer);
+        writer.writeln("");
+        writer.dedent();
+    }
+}
+
 trait AstDisplay {
     fn display(&self, writer: &mut AstDisplayWriter);
 }
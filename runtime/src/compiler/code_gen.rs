@@ -25,6 +25,12 @@ impl Updateable {
 
         Ok(())
     }
+
+    pub fn update_spawn(&self, code: &mut CodeGen, relative_offset: i32) -> Result<()> {
+        code.code[self.index] = OpCode::Spawn { relative_offset: relative_offset.try_into()? };
+
+        Ok(())
+    }
 }
 
 impl CodeGen {
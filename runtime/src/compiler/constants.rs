@@ -0,0 +1,31 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use super::variables::Variables;
+
+pub struct Constants {
+    by_name: BTreeMap<String, i32>,
+}
+
+impl Constants {
+    pub fn new(def: BTreeMap<String, i32>, variables: &Variables) -> Result<Self> {
+        for name in def.keys() {
+            if variables.contains(name) {
+                anyhow::bail!("Constant has the same name as a variable: {}", name);
+            }
+        }
+
+        Ok(Self { by_name: def })
+    }
+
+    pub fn get_value(&self, name: &str) -> Result<i32> {
+        self.by_name
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Constant not found: {}", name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+}
@@ -63,7 +63,7 @@ impl LoopManagerStack {
         Self { stack: Vec::new() }
     }
 
-    pub fn end(self) -> Result<()> {
+    pub fn end(&self) -> Result<()> {
         if !self.stack.is_empty() {
             return Err(anyhow::anyhow!("LoopManagerStack not empty"));
         }
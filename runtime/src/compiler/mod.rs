@@ -1,15 +1,19 @@
 mod ast;
 mod code_gen;
+mod constants;
 mod loop_manager;
+mod parameters;
 mod transformers;
 mod variables;
 
 use code_gen::{CodeGen, Updateable};
+use constants::Constants;
 use log::info;
 use loop_manager::LoopManagerStack;
+use parameters::Parameters;
 use variables::Variables;
 
-use crate::vm::{executable::{Executable, OpCode}, i24::i24};
+use crate::vm::{executable::{DebugInfo, EntryPoint, Event, Executable, OpCode, StatementInfo}, i24::i24, palettes};
 
 use anyhow::Result;
 use ast::Program;
@@ -26,9 +30,16 @@ pub fn compile(input: &str) -> Result<String> {
     info!("After transformations:\n{}", program);
 
     let variables = Variables::new(program.variables)?;
-    let mut compiler = Compiler::new(variables);
+    let constants = Constants::new(program.constants, &variables)?;
+    let parameters = Parameters::new(program.parameters, &variables, &constants)?;
+    let mut compiler = Compiler::new(variables, constants, parameters);
+
+    compiler.main(&program.body)?;
+
+    for handler in program.handlers.iter() {
+        compiler.handler(handler)?;
+    }
 
-    compiler.node(&program.body)?;
     let exec = compiler.generate()?;
 
     info!("Compiled into executable:\n{}", exec);
@@ -39,31 +50,82 @@ pub fn compile(input: &str) -> Result<String> {
 struct Compiler {
     code: CodeGen,
     variables: Variables,
+    constants: Constants,
+    parameters: Parameters,
+    entry_points: Vec<EntryPoint>,
+    statements: Vec<StatementInfo>,
     loop_manager_stack: LoopManagerStack,
 }
 
 impl Compiler {
-    pub fn new(variables: Variables) -> Self {
+    pub fn new(variables: Variables, constants: Constants, parameters: Parameters) -> Self {
         Compiler {
             code: CodeGen::new(),
             variables,
+            constants,
+            parameters,
+            entry_points: Vec::new(),
+            statements: Vec::new(),
             loop_manager_stack: LoopManagerStack::new(),
         }
     }
 
-    pub fn generate(self) -> Result<Executable> {
+    pub fn generate(mut self) -> Result<Executable> {
         self.loop_manager_stack.end()?;
 
+        self.statements.sort_by_key(|statement| statement.address);
+        let debug_info = DebugInfo {
+            variables: self.variables.names(),
+            statements: self.statements,
+        };
+
         Ok(Executable::new(
             STACK_SIZE as u32,
             self.variables.len() as u32,
+            self.parameters.definitions(),
+            self.entry_points,
+            debug_info,
             self.code.build(),
         ))
     }
 
+    pub fn main(&mut self, body: &ast::Node) -> Result<()> {
+        self.node(body)?;
+
+        // end of the main task
+        self.code.emit(OpCode::Exit);
+
+        Ok(())
+    }
+
+    pub fn handler(&mut self, handler: &ast::Handler) -> Result<()> {
+        let event = match handler.event {
+            ast::Event::Start => Event::Start,
+            ast::Event::Timer { period } => {
+                if period == 0 {
+                    anyhow::bail!("Timer period must be positive");
+                }
+
+                Event::Timer { period }
+            }
+            ast::Event::Input { channel } => Event::Input { channel },
+        };
+
+        self.entry_points.push(EntryPoint {
+            event,
+            address: self.code.current_index() as u32,
+        });
+
+        self.node(&handler.body)?;
+        self.code.emit(OpCode::Exit);
+
+        Ok(())
+    }
+
     pub fn node(&mut self, node: &ast::Node) -> Result<()> {
         match node {
             ast::Node::Sequence(sequence) => self.sequence(sequence),
+            ast::Node::Block(block) => self.block(block),
             ast::Node::Naked(naked) => self.naked(naked),
             ast::Node::Compare(compare) => self.compare(compare),
             ast::Node::Logic(logic) => self.logic(logic),
@@ -76,12 +138,43 @@ impl Compiler {
             ast::Node::Literal(literal) => self.literal(literal),
             ast::Node::Arithmetic(arithmetic) => self.arithmetic(arithmetic),
             ast::Node::Rand(rand) => self.rand(rand),
+            ast::Node::Seed(seed) => self.seed(seed),
+            ast::Node::Noise1D(noise) => self.noise1d(noise),
+            ast::Node::Noise2D(noise) => self.noise2d(noise),
             ast::Node::GetVariable(get_variable) => self.get_variable(get_variable),
             ast::Node::SetVariable(set_variable) => self.set_variable(set_variable),
+            ast::Node::GetConstant(get_constant) => self.get_constant(get_constant),
+            ast::Node::GetParameter(get_parameter) => self.get_parameter(get_parameter),
             ast::Node::Len(len) => self.len(len),
+            ast::Node::PosX(pos_x) => self.pos_x(pos_x),
+            ast::Node::PosY(pos_y) => self.pos_y(pos_y),
+            ast::Node::Width(width) => self.width(width),
+            ast::Node::Height(height) => self.height(height),
             ast::Node::Get(get) => self.get(get),
             ast::Node::Set(set) => self.set(set),
+            ast::Node::Rgb(rgb) => self.rgb(rgb),
+            ast::Node::Hsv(hsv) => self.hsv(hsv),
+            ast::Node::Hsl(hsl) => self.hsl(hsl),
+            ast::Node::ColorChannel(color_channel) => self.color_channel(color_channel),
+            ast::Node::GetColor(get_color) => self.get_color(get_color),
+            ast::Node::SetColor(set_color) => self.set_color(set_color),
+            ast::Node::SetXY(set_xy) => self.set_xy(set_xy),
+            ast::Node::Blend(blend) => self.blend(blend),
+            ast::Node::Scale(scale) => self.scale(scale),
+            ast::Node::AddSat(add_sat) => self.add_sat(add_sat),
+            ast::Node::PaletteLookup(palette_lookup) => self.palette_lookup(palette_lookup),
+            ast::Node::FadeAll(fade_all) => self.fade_all(fade_all),
+            ast::Node::Fill(fill) => self.fill(fill),
+            ast::Node::Gradient(gradient) => self.gradient(gradient),
+            ast::Node::Shift(shift) => self.shift(shift),
+            ast::Node::Rotate(rotate) => self.rotate(rotate),
+            ast::Node::Mirror(mirror) => self.mirror(mirror),
             ast::Node::Sleep(sleep) => self.sleep(sleep),
+            ast::Node::Input(input) => self.input(input),
+            ast::Node::Now(now) => self.now(now),
+            ast::Node::Elapsed(elapsed) => self.elapsed(elapsed),
+            ast::Node::WaitFrame(wait_frame) => self.wait_frame(wait_frame),
+            ast::Node::Spawn(spawn) => self.spawn(spawn),
             _ => {
                 anyhow::bail!("Unexpected node: {:?}", node);
             }
@@ -96,6 +189,15 @@ impl Compiler {
         Ok(())
     }
 
+    fn block(&mut self, block: &ast::Block) -> Result<()> {
+        self.statements.push(StatementInfo {
+            address: self.code.current_index() as u32,
+            id: block.id.clone(),
+        });
+
+        self.node(&block.body)
+    }
+
     fn naked(&mut self, naked: &ast::Naked) -> Result<()> {
         self.node(&naked.value)?;
         self.code.emit(OpCode::Pop);
@@ -193,6 +295,53 @@ impl Compiler {
         Ok(())
     }
 
+    fn input(&mut self, input: &ast::Input) -> Result<()> {
+        self.node(&input.channel)?;
+        self.code.emit(OpCode::Input);
+
+        Ok(())
+    }
+
+    fn now(&mut self, _now: &ast::Now) -> Result<()> {
+        self.code.emit(OpCode::Now);
+
+        Ok(())
+    }
+
+    fn elapsed(&mut self, _elapsed: &ast::Elapsed) -> Result<()> {
+        self.code.emit(OpCode::Elapsed);
+
+        Ok(())
+    }
+
+    fn wait_frame(&mut self, _wait_frame: &ast::WaitFrame) -> Result<()> {
+        self.code.emit(OpCode::WaitFrame);
+
+        Ok(())
+    }
+
+    fn spawn(&mut self, spawn: &ast::Spawn) -> Result<()> {
+        let spawn_op = self.code.emit(OpCode::Spawn { relative_offset: i24::ZERO });
+        let skip_jump = self.code.emit(OpCode::Jump { relative_offset: i24::ZERO });
+
+        let offset = spawn_op.compute_relative_offset(self.code.current_index());
+        spawn_op.update_spawn(&mut self.code, offset)?;
+
+        // the task body cannot break or continue loops of the spawning task
+        let loop_manager_stack = std::mem::replace(&mut self.loop_manager_stack, LoopManagerStack::new());
+
+        self.node(&spawn.body)?;
+        self.code.emit(OpCode::Exit);
+
+        self.loop_manager_stack.end()?;
+        self.loop_manager_stack = loop_manager_stack;
+
+        let offset = skip_jump.compute_relative_offset(self.code.current_index());
+        skip_jump.update_jump(&mut self.code, offset)?;
+
+        Ok(())
+    }
+
     fn break_(&mut self, _break: &ast::Break) -> Result<()> {
         self.loop_manager_stack.emit_break(&mut self.code)
     }
@@ -233,6 +382,30 @@ impl Compiler {
         Ok(())
     }
 
+    fn seed(&mut self, seed: &ast::Seed) -> Result<()> {
+        self.node(&seed.seed)?;
+        self.code.emit(OpCode::Seed);
+
+        Ok(())
+    }
+
+    fn noise1d(&mut self, noise: &ast::Noise1D) -> Result<()> {
+        self.node(&noise.x)?;
+        self.node(&noise.seed)?;
+        self.code.emit(OpCode::Noise1D);
+
+        Ok(())
+    }
+
+    fn noise2d(&mut self, noise: &ast::Noise2D) -> Result<()> {
+        self.node(&noise.x)?;
+        self.node(&noise.y)?;
+        self.node(&noise.seed)?;
+        self.code.emit(OpCode::Noise2D);
+
+        Ok(())
+    }
+
     fn get_variable(&mut self, get_variable: &ast::GetVariable) -> Result<()> {
         self.code.emit(OpCode::PushVariable {
             index: self.variables.get_index(&get_variable.variable)?,
@@ -242,6 +415,14 @@ impl Compiler {
     }
 
     fn set_variable(&mut self, set_variable: &ast::SetVariable) -> Result<()> {
+        if self.constants.contains(&set_variable.variable) {
+            anyhow::bail!("Cannot assign to constant: {}", set_variable.variable);
+        }
+
+        if self.parameters.contains(&set_variable.variable) {
+            anyhow::bail!("Cannot assign to parameter: {}", set_variable.variable);
+        }
+
         self.node(&set_variable.value)?;
         self.code.emit(OpCode::PopVariable {
             index: self.variables.get_index(&set_variable.variable)?,
@@ -250,19 +431,62 @@ impl Compiler {
         Ok(())
     }
 
+    fn get_constant(&mut self, get_constant: &ast::GetConstant) -> Result<()> {
+        // constants are inlined, they don't use any variable slot
+        self.code.emit(OpCode::PushConstant {
+            value: self.constants.get_value(&get_constant.constant)?.try_into()?,
+        });
+
+        Ok(())
+    }
+
+    fn get_parameter(&mut self, get_parameter: &ast::GetParameter) -> Result<()> {
+        self.code.emit(OpCode::ParamGet {
+            index: self.parameters.get_index(&get_parameter.parameter)?,
+        });
+
+        Ok(())
+    }
+
     fn len(&mut self, _len: &ast::Len) -> Result<()> {
         self.code.emit(OpCode::Len);
 
         Ok(())
     }
 
+    fn pos_x(&mut self, pos_x: &ast::PosX) -> Result<()> {
+        self.node(&pos_x.index)?;
+        self.code.emit(OpCode::PosX);
+
+        Ok(())
+    }
+
+    fn pos_y(&mut self, pos_y: &ast::PosY) -> Result<()> {
+        self.node(&pos_y.index)?;
+        self.code.emit(OpCode::PosY);
+
+        Ok(())
+    }
+
+    fn width(&mut self, _width: &ast::Width) -> Result<()> {
+        self.code.emit(OpCode::Width);
+
+        Ok(())
+    }
+
+    fn height(&mut self, _height: &ast::Height) -> Result<()> {
+        self.code.emit(OpCode::Height);
+
+        Ok(())
+    }
+
     fn get(&mut self, get: &ast::Get) -> Result<()> {
         self.node(&get.index)?;
 
         match get.color {
-            ast::GetColor::Red => self.code.emit(OpCode::GetRed),
-            ast::GetColor::Green => self.code.emit(OpCode::GetGreen),
-            ast::GetColor::Blue => self.code.emit(OpCode::GetBlue),
+            ast::Channel::Red => self.code.emit(OpCode::GetRed),
+            ast::Channel::Green => self.code.emit(OpCode::GetGreen),
+            ast::Channel::Blue => self.code.emit(OpCode::GetBlue),
         };
 
         Ok(())
@@ -278,6 +502,152 @@ impl Compiler {
         Ok(())
     }
 
+    fn rgb(&mut self, rgb: &ast::Rgb) -> Result<()> {
+        self.node(&rgb.red)?;
+        self.node(&rgb.green)?;
+        self.node(&rgb.blue)?;
+        self.code.emit(OpCode::Rgb);
+
+        Ok(())
+    }
+
+    fn hsv(&mut self, hsv: &ast::Hsv) -> Result<()> {
+        self.node(&hsv.hue)?;
+        self.node(&hsv.saturation)?;
+        self.node(&hsv.value)?;
+        self.code.emit(OpCode::Hsv);
+
+        Ok(())
+    }
+
+    fn hsl(&mut self, hsl: &ast::Hsl) -> Result<()> {
+        self.node(&hsl.hue)?;
+        self.node(&hsl.saturation)?;
+        self.node(&hsl.lightness)?;
+        self.code.emit(OpCode::Hsl);
+
+        Ok(())
+    }
+
+    fn color_channel(&mut self, color_channel: &ast::ColorChannel) -> Result<()> {
+        self.node(&color_channel.color)?;
+
+        match color_channel.channel {
+            ast::Channel::Red => self.code.emit(OpCode::ColorRed),
+            ast::Channel::Green => self.code.emit(OpCode::ColorGreen),
+            ast::Channel::Blue => self.code.emit(OpCode::ColorBlue),
+        };
+
+        Ok(())
+    }
+
+    fn get_color(&mut self, get_color: &ast::GetColor) -> Result<()> {
+        self.node(&get_color.index)?;
+        self.code.emit(OpCode::GetColor);
+
+        Ok(())
+    }
+
+    fn set_color(&mut self, set_color: &ast::SetColor) -> Result<()> {
+        self.node(&set_color.index)?;
+        self.node(&set_color.color)?;
+        self.code.emit(OpCode::SetColor);
+
+        Ok(())
+    }
+
+    fn set_xy(&mut self, set_xy: &ast::SetXY) -> Result<()> {
+        self.node(&set_xy.x)?;
+        self.node(&set_xy.y)?;
+        self.node(&set_xy.color)?;
+        self.code.emit(OpCode::SetXY);
+
+        Ok(())
+    }
+
+    fn blend(&mut self, blend: &ast::Blend) -> Result<()> {
+        self.node(&blend.color1)?;
+        self.node(&blend.color2)?;
+        self.node(&blend.amount)?;
+        self.code.emit(OpCode::Blend);
+
+        Ok(())
+    }
+
+    fn scale(&mut self, scale: &ast::Scale) -> Result<()> {
+        self.node(&scale.color)?;
+        self.node(&scale.amount)?;
+        self.code.emit(OpCode::Scale);
+
+        Ok(())
+    }
+
+    fn add_sat(&mut self, add_sat: &ast::AddSat) -> Result<()> {
+        self.node(&add_sat.color1)?;
+        self.node(&add_sat.color2)?;
+        self.code.emit(OpCode::AddSat);
+
+        Ok(())
+    }
+
+    fn palette_lookup(&mut self, palette_lookup: &ast::PaletteLookup) -> Result<()> {
+        let palette = palettes::find(&palette_lookup.palette)
+            .ok_or_else(|| anyhow::anyhow!("Unknown palette: {}", palette_lookup.palette))?;
+
+        self.node(&palette_lookup.position)?;
+        self.code.emit(OpCode::PaletteLookup { palette: palette as u8 });
+
+        Ok(())
+    }
+
+    fn fade_all(&mut self, fade_all: &ast::FadeAll) -> Result<()> {
+        self.node(&fade_all.amount)?;
+        self.code.emit(OpCode::FadeAll);
+
+        Ok(())
+    }
+
+    fn fill(&mut self, fill: &ast::Fill) -> Result<()> {
+        self.node(&fill.start)?;
+        self.node(&fill.end)?;
+        self.node(&fill.color)?;
+        self.code.emit(OpCode::Fill);
+
+        Ok(())
+    }
+
+    fn gradient(&mut self, gradient: &ast::Gradient) -> Result<()> {
+        self.node(&gradient.start)?;
+        self.node(&gradient.end)?;
+        self.node(&gradient.color1)?;
+        self.node(&gradient.color2)?;
+        self.code.emit(OpCode::Gradient);
+
+        Ok(())
+    }
+
+    fn shift(&mut self, shift: &ast::Shift) -> Result<()> {
+        self.node(&shift.offset)?;
+        self.code.emit(OpCode::Shift);
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, rotate: &ast::Rotate) -> Result<()> {
+        self.node(&rotate.offset)?;
+        self.code.emit(OpCode::Rotate);
+
+        Ok(())
+    }
+
+    fn mirror(&mut self, mirror: &ast::Mirror) -> Result<()> {
+        self.node(&mirror.start)?;
+        self.node(&mirror.end)?;
+        self.code.emit(OpCode::Mirror);
+
+        Ok(())
+    }
+
     fn sleep(&mut self, sleep: &ast::Sleep) -> Result<()> {
         self.node(&sleep.delay)?;
         self.code.emit(OpCode::Sleep);
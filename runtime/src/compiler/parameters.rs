@@ -0,0 +1,63 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use super::{ast, constants::Constants, variables::Variables};
+use crate::vm::executable::ParameterDefinition;
+
+pub struct Parameters {
+    by_name: HashMap<String, u8>,
+    by_index: Vec<ParameterDefinition>,
+}
+
+impl Parameters {
+    pub fn new(def: Vec<ast::Parameter>, variables: &Variables, constants: &Constants) -> Result<Self> {
+        if def.len() > 255 {
+            anyhow::bail!("Too many parameters! Maximum is 255.");
+        }
+
+        let mut by_name = HashMap::new();
+        let mut by_index = Vec::new();
+
+        for (index, parameter) in def.into_iter().enumerate() {
+            if variables.contains(&parameter.name) || constants.contains(&parameter.name) {
+                anyhow::bail!("Parameter has the same name as a variable or a constant: {}", parameter.name);
+            }
+
+            if parameter.min > parameter.max {
+                anyhow::bail!("Parameter {} has min greater than max", parameter.name);
+            }
+
+            if !(parameter.min..=parameter.max).contains(&parameter.default) {
+                anyhow::bail!("Parameter {} has default value out of range", parameter.name);
+            }
+
+            if by_name.insert(parameter.name.clone(), index as u8).is_some() {
+                anyhow::bail!("Duplicate parameter: {}", parameter.name);
+            }
+
+            by_index.push(ParameterDefinition {
+                name: parameter.name,
+                default: parameter.default,
+                min: parameter.min,
+                max: parameter.max,
+            });
+        }
+
+        Ok(Self { by_name, by_index })
+    }
+
+    pub fn get_index(&self, name: &str) -> Result<u8> {
+        self.by_name
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Parameter not found: {}", name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    pub fn definitions(&self) -> Vec<ParameterDefinition> {
+        self.by_index.clone()
+    }
+}
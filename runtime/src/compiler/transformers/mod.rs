@@ -15,9 +15,14 @@ use loops::Loops;
 pub fn transform(program: &mut Program) -> Result<()> {
     let variable_allocator = RefCell::new(VariableAllocator::new(&mut program.variables));
 
-    Loops::new(&variable_allocator).transform_inplace(&mut program.body)?;
-    Between::new(&variable_allocator).transform_inplace(&mut program.body)?;
-    Compare::new(&variable_allocator).transform_inplace(&mut program.body)?;
+    let mut bodies = vec![&mut program.body];
+    bodies.extend(program.handlers.iter_mut().map(|handler| &mut handler.body));
+
+    for body in bodies {
+        Loops::new(&variable_allocator).transform_inplace(body)?;
+        Between::new(&variable_allocator).transform_inplace(body)?;
+        Compare::new(&variable_allocator).transform_inplace(body)?;
+    }
 
     Ok(())
 }
@@ -42,6 +47,7 @@ trait Transformer {
     fn transform(&mut self, node: ast::Node) -> Result<ast::Node> {
         match node {
             ast::Node::Sequence(sequence) => self.transform_sequence(sequence),
+            ast::Node::Block(block) => self.transform_block(block),
             ast::Node::Naked(naked) => self.transform_naked(naked),
             ast::Node::Compare(compare) => self.transform_compare(compare),
             ast::Node::Logic(logic) => self.transform_logic(logic),
@@ -61,12 +67,43 @@ trait Transformer {
             ast::Node::Arithmetic(arithmetic) => self.transform_arithmetic(arithmetic),
             ast::Node::Between(between) => self.transform_between(between),
             ast::Node::Rand(rand) => self.transform_rand(rand),
+            ast::Node::Seed(seed) => self.transform_seed(seed),
+            ast::Node::Noise1D(noise) => self.transform_noise1d(noise),
+            ast::Node::Noise2D(noise) => self.transform_noise2d(noise),
             ast::Node::GetVariable(get_variable) => self.transform_get_variable(get_variable),
             ast::Node::SetVariable(set_variable) => self.transform_set_variable(set_variable),
+            ast::Node::GetConstant(get_constant) => self.transform_get_constant(get_constant),
+            ast::Node::GetParameter(get_parameter) => self.transform_get_parameter(get_parameter),
             ast::Node::Len(len) => self.transform_len(len),
+            ast::Node::PosX(pos_x) => self.transform_pos_x(pos_x),
+            ast::Node::PosY(pos_y) => self.transform_pos_y(pos_y),
+            ast::Node::Width(width) => self.transform_width(width),
+            ast::Node::Height(height) => self.transform_height(height),
             ast::Node::Get(get) => self.transform_get(get),
             ast::Node::Set(set) => self.transform_set(set),
+            ast::Node::Rgb(rgb) => self.transform_rgb(rgb),
+            ast::Node::Hsv(hsv) => self.transform_hsv(hsv),
+            ast::Node::Hsl(hsl) => self.transform_hsl(hsl),
+            ast::Node::ColorChannel(color_channel) => self.transform_color_channel(color_channel),
+            ast::Node::GetColor(get_color) => self.transform_get_color(get_color),
+            ast::Node::SetColor(set_color) => self.transform_set_color(set_color),
+            ast::Node::SetXY(set_xy) => self.transform_set_xy(set_xy),
+            ast::Node::Blend(blend) => self.transform_blend(blend),
+            ast::Node::Scale(scale) => self.transform_scale(scale),
+            ast::Node::AddSat(add_sat) => self.transform_add_sat(add_sat),
+            ast::Node::PaletteLookup(palette_lookup) => self.transform_palette_lookup(palette_lookup),
+            ast::Node::FadeAll(fade_all) => self.transform_fade_all(fade_all),
+            ast::Node::Fill(fill) => self.transform_fill(fill),
+            ast::Node::Gradient(gradient) => self.transform_gradient(gradient),
+            ast::Node::Shift(shift) => self.transform_shift(shift),
+            ast::Node::Rotate(rotate) => self.transform_rotate(rotate),
+            ast::Node::Mirror(mirror) => self.transform_mirror(mirror),
             ast::Node::Sleep(sleep) => self.transform_sleep(sleep),
+            ast::Node::Input(input) => self.transform_input(input),
+            ast::Node::Now(now) => self.transform_now(now),
+            ast::Node::Elapsed(elapsed) => self.transform_elapsed(elapsed),
+            ast::Node::WaitFrame(wait_frame) => self.transform_wait_frame(wait_frame),
+            ast::Node::Spawn(spawn) => self.transform_spawn(spawn),
         }
     }
 
@@ -89,6 +126,12 @@ trait Transformer {
         Ok(ast::Node::Sequence(sequence))
     }
 
+    fn transform_block(&mut self, mut block: ast::Block) -> Result<ast::Node> {
+        self.transform_inplace(&mut block.body)?;
+
+        Ok(ast::Node::Block(block))
+    }
+
     fn transform_naked(&mut self, mut naked: ast::Naked) -> Result<ast::Node> {
         self.transform_inplace(&mut naked.value)?;
 
@@ -203,6 +246,27 @@ trait Transformer {
         Ok(ast::Node::Rand(rand))
     }
 
+    fn transform_seed(&mut self, mut seed: ast::Seed) -> Result<ast::Node> {
+        self.transform_inplace(&mut seed.seed)?;
+
+        Ok(ast::Node::Seed(seed))
+    }
+
+    fn transform_noise1d(&mut self, mut noise: ast::Noise1D) -> Result<ast::Node> {
+        self.transform_inplace(&mut noise.x)?;
+        self.transform_inplace(&mut noise.seed)?;
+
+        Ok(ast::Node::Noise1D(noise))
+    }
+
+    fn transform_noise2d(&mut self, mut noise: ast::Noise2D) -> Result<ast::Node> {
+        self.transform_inplace(&mut noise.x)?;
+        self.transform_inplace(&mut noise.y)?;
+        self.transform_inplace(&mut noise.seed)?;
+
+        Ok(ast::Node::Noise2D(noise))
+    }
+
     fn transform_get_variable(&mut self, get_variable: ast::GetVariable) -> Result<ast::Node> {
         Ok(ast::Node::GetVariable(get_variable))
     }
@@ -213,10 +277,38 @@ trait Transformer {
         Ok(ast::Node::SetVariable(set_variable))
     }
 
+    fn transform_get_constant(&mut self, get_constant: ast::GetConstant) -> Result<ast::Node> {
+        Ok(ast::Node::GetConstant(get_constant))
+    }
+
+    fn transform_get_parameter(&mut self, get_parameter: ast::GetParameter) -> Result<ast::Node> {
+        Ok(ast::Node::GetParameter(get_parameter))
+    }
+
     fn transform_len(&mut self, len: ast::Len) -> Result<ast::Node> {
         Ok(ast::Node::Len(len))
     }
 
+    fn transform_pos_x(&mut self, mut pos_x: ast::PosX) -> Result<ast::Node> {
+        self.transform_inplace(&mut pos_x.index)?;
+
+        Ok(ast::Node::PosX(pos_x))
+    }
+
+    fn transform_pos_y(&mut self, mut pos_y: ast::PosY) -> Result<ast::Node> {
+        self.transform_inplace(&mut pos_y.index)?;
+
+        Ok(ast::Node::PosY(pos_y))
+    }
+
+    fn transform_width(&mut self, width: ast::Width) -> Result<ast::Node> {
+        Ok(ast::Node::Width(width))
+    }
+
+    fn transform_height(&mut self, height: ast::Height) -> Result<ast::Node> {
+        Ok(ast::Node::Height(height))
+    }
+
     fn transform_get(&mut self, mut get: ast::Get) -> Result<ast::Node> {
         self.transform_inplace(&mut get.index)?;
 
@@ -231,9 +323,154 @@ trait Transformer {
         Ok(ast::Node::Set(set))
     }
 
+    fn transform_rgb(&mut self, mut rgb: ast::Rgb) -> Result<ast::Node> {
+        self.transform_inplace(&mut rgb.red)?;
+        self.transform_inplace(&mut rgb.green)?;
+        self.transform_inplace(&mut rgb.blue)?;
+
+        Ok(ast::Node::Rgb(rgb))
+    }
+
+    fn transform_hsv(&mut self, mut hsv: ast::Hsv) -> Result<ast::Node> {
+        self.transform_inplace(&mut hsv.hue)?;
+        self.transform_inplace(&mut hsv.saturation)?;
+        self.transform_inplace(&mut hsv.value)?;
+
+        Ok(ast::Node::Hsv(hsv))
+    }
+
+    fn transform_hsl(&mut self, mut hsl: ast::Hsl) -> Result<ast::Node> {
+        self.transform_inplace(&mut hsl.hue)?;
+        self.transform_inplace(&mut hsl.saturation)?;
+        self.transform_inplace(&mut hsl.lightness)?;
+
+        Ok(ast::Node::Hsl(hsl))
+    }
+
+    fn transform_color_channel(&mut self, mut color_channel: ast::ColorChannel) -> Result<ast::Node> {
+        self.transform_inplace(&mut color_channel.color)?;
+
+        Ok(ast::Node::ColorChannel(color_channel))
+    }
+
+    fn transform_get_color(&mut self, mut get_color: ast::GetColor) -> Result<ast::Node> {
+        self.transform_inplace(&mut get_color.index)?;
+
+        Ok(ast::Node::GetColor(get_color))
+    }
+
+    fn transform_set_color(&mut self, mut set_color: ast::SetColor) -> Result<ast::Node> {
+        self.transform_inplace(&mut set_color.index)?;
+        self.transform_inplace(&mut set_color.color)?;
+
+        Ok(ast::Node::SetColor(set_color))
+    }
+
+    fn transform_set_xy(&mut self, mut set_xy: ast::SetXY) -> Result<ast::Node> {
+        self.transform_inplace(&mut set_xy.x)?;
+        self.transform_inplace(&mut set_xy.y)?;
+        self.transform_inplace(&mut set_xy.color)?;
+
+        Ok(ast::Node::SetXY(set_xy))
+    }
+
+    fn transform_blend(&mut self, mut blend: ast::Blend) -> Result<ast::Node> {
+        self.transform_inplace(&mut blend.color1)?;
+        self.transform_inplace(&mut blend.color2)?;
+        self.transform_inplace(&mut blend.amount)?;
+
+        Ok(ast::Node::Blend(blend))
+    }
+
+    fn transform_scale(&mut self, mut scale: ast::Scale) -> Result<ast::Node> {
+        self.transform_inplace(&mut scale.color)?;
+        self.transform_inplace(&mut scale.amount)?;
+
+        Ok(ast::Node::Scale(scale))
+    }
+
+    fn transform_add_sat(&mut self, mut add_sat: ast::AddSat) -> Result<ast::Node> {
+        self.transform_inplace(&mut add_sat.color1)?;
+        self.transform_inplace(&mut add_sat.color2)?;
+
+        Ok(ast::Node::AddSat(add_sat))
+    }
+
+    fn transform_palette_lookup(&mut self, mut palette_lookup: ast::PaletteLookup) -> Result<ast::Node> {
+        self.transform_inplace(&mut palette_lookup.position)?;
+
+        Ok(ast::Node::PaletteLookup(palette_lookup))
+    }
+
+    fn transform_fade_all(&mut self, mut fade_all: ast::FadeAll) -> Result<ast::Node> {
+        self.transform_inplace(&mut fade_all.amount)?;
+
+        Ok(ast::Node::FadeAll(fade_all))
+    }
+
+    fn transform_fill(&mut self, mut fill: ast::Fill) -> Result<ast::Node> {
+        self.transform_inplace(&mut fill.start)?;
+        self.transform_inplace(&mut fill.end)?;
+        self.transform_inplace(&mut fill.color)?;
+
+        Ok(ast::Node::Fill(fill))
+    }
+
+    fn transform_gradient(&mut self, mut gradient: ast::Gradient) -> Result<ast::Node> {
+        self.transform_inplace(&mut gradient.start)?;
+        self.transform_inplace(&mut gradient.end)?;
+        self.transform_inplace(&mut gradient.color1)?;
+        self.transform_inplace(&mut gradient.color2)?;
+
+        Ok(ast::Node::Gradient(gradient))
+    }
+
+    fn transform_shift(&mut self, mut shift: ast::Shift) -> Result<ast::Node> {
+        self.transform_inplace(&mut shift.offset)?;
+
+        Ok(ast::Node::Shift(shift))
+    }
+
+    fn transform_rotate(&mut self, mut rotate: ast::Rotate) -> Result<ast::Node> {
+        self.transform_inplace(&mut rotate.offset)?;
+
+        Ok(ast::Node::Rotate(rotate))
+    }
+
+    fn transform_mirror(&mut self, mut mirror: ast::Mirror) -> Result<ast::Node> {
+        self.transform_inplace(&mut mirror.start)?;
+        self.transform_inplace(&mut mirror.end)?;
+
+        Ok(ast::Node::Mirror(mirror))
+    }
+
     fn transform_sleep(&mut self, mut sleep: ast::Sleep) -> Result<ast::Node> {
         self.transform_inplace(&mut sleep.delay)?;
 
         Ok(ast::Node::Sleep(sleep))
     }
+
+    fn transform_input(&mut self, mut input: ast::Input) -> Result<ast::Node> {
+        self.transform_inplace(&mut input.channel)?;
+
+        Ok(ast::Node::Input(input))
+    }
+
+    fn transform_now(&mut self, now: ast::Now) -> Result<ast::Node> {
+        Ok(ast::Node::Now(now))
+    }
+
+    fn transform_elapsed(&mut self, elapsed: ast::Elapsed) -> Result<ast::Node> {
+        Ok(ast::Node::Elapsed(elapsed))
+    }
+
+    fn transform_wait_frame(&mut self, wait_frame: ast::WaitFrame) -> Result<ast::Node> {
+        Ok(ast::Node::WaitFrame(wait_frame))
+    }
+
+    fn transform_spawn(&mut self, mut spawn: ast::Spawn) -> Result<ast::Node> {
+        self.transform_inplace(&mut spawn.body)?;
+
+        Ok(ast::Node::Spawn(spawn))
+    }
 }
@@ -29,6 +29,10 @@ impl Variables {
             .ok_or_else(|| anyhow::anyhow!("Variable not found: {}", name))
     }
 
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
     #[allow(dead_code)]
     pub fn get_name(&self, index: u8) -> Result<&str> {
         self.by_index
@@ -37,6 +41,10 @@ impl Variables {
             .ok_or_else(|| anyhow::anyhow!("Variable not found: {}", index))
     }
 
+    pub fn names(&self) -> Vec<String> {
+        self.by_index.clone()
+    }
+
     pub fn len(&self) -> usize {
         self.by_index.len()
     }
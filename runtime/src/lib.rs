@@ -3,10 +3,26 @@ mod fps_printer;
 mod compiler;
 mod vm;
 
-use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::{Deref, DerefMut, Range},
+    sync::{Arc, LazyLock, Mutex, MutexGuard},
+    time::Duration,
+};
 
-use render::{Color, Scene};
-use vm::executable::Executable;
+use render::{
+    compositor::BlendMode,
+    layout::{Matrix, Position, Wiring},
+    output::Calibration,
+    power::PowerModel,
+    transition::Transition,
+    strip, Color, Layout, Scene, Segment,
+};
+use vm::{
+    clock::{Clock, ManualClock, RealTimeClock, SimulationClock},
+    executable::Executable,
+    trace::{RecordingApi, RecordingClock, ReplayApi, ReplayClock, Trace, TraceRecorder, TraceReplayer},
+};
 use wasm_bindgen::prelude::*;
 use js_sys::{Math, Uint8ClampedArray};
 use fps_printer::FpsPrinter;
@@ -14,43 +30,279 @@ use fps_printer::FpsPrinter;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-static SCENE : LazyLock<Mutex<Scene>> = LazyLock::new(|| Mutex::new(Scene::new()));
-static VM: LazyLock<Mutex<vm::VM>> = LazyLock::new(|| Mutex::new(vm::VM::new(Box::new(VMApi))));
+static SCENE : LazyLock<Arc<Mutex<Scene>>> = LazyLock::new(|| Arc::new(Mutex::new(Scene::new())));
+static CLOCK: LazyLock<SimulationClock> = LazyLock::new(|| SimulationClock::new(Box::new(RealTimeClock::new())));
+static PLAYERS: LazyLock<Mutex<Players>> = LazyLock::new(|| Mutex::new(Players::new()));
+static INPUTS: LazyLock<Mutex<HashMap<u32, i32>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static SESSION: LazyLock<Mutex<Session>> = LazyLock::new(|| Mutex::new(Session::Live));
 static FPS_PRINTER: FpsPrinter = FpsPrinter::new();
 
+// Lock order: session, then players, then scene
+
+// A recording or replay is bound to the VM of one layer
+enum Session {
+    Live,
+    Recording { layer: usize, recorder: TraceRecorder, executable: String, seed: u64, len: usize },
+    Replaying { layer: usize, replayer: TraceReplayer },
+}
+
+impl Session {
+    fn layer(&self) -> Option<usize> {
+        match self {
+            Session::Live => None,
+            Session::Recording { layer, .. } | Session::Replaying { layer, .. } => Some(*layer),
+        }
+    }
+}
+
+// One VM per layer of the scene, by layer id
+struct Players {
+    vms: BTreeMap<usize, vm::VM>,
+    // previous programs of the layers in transition
+    outgoing: BTreeMap<usize, vm::VM>,
+    // target of the parameters and debugger functions
+    selected: usize,
+    max_sleep: Option<Duration>,
+    transition: Transition,
+}
+
+impl Players {
+    fn new() -> Self {
+        let mut players = Self {
+            vms: BTreeMap::new(),
+            outgoing: BTreeMap::new(),
+            selected: 0,
+            max_sleep: None,
+            transition: Transition::Cut,
+        };
+
+        players.sync(&get_scene());
+        players
+    }
+
+    // one VM per layer: VMs of removed layers are dropped, new layers get a stopped VM
+    // previous programs are dropped once their layer is out of transition
+    fn sync(&mut self, scene: &Scene) {
+        let compositor = scene.compositor();
+        let layers: Vec<usize> = compositor.layers().iter().map(|layer| layer.id()).collect();
+        let max_sleep = self.max_sleep;
+
+        self.vms.retain(|layer, _| layers.contains(layer));
+        self.outgoing
+            .retain(|layer, _| layers.contains(layer) && compositor.layer(*layer).in_transition());
+
+        for layer in layers.iter() {
+            let buffer = compositor.layer(*layer).active();
+            self.vms.entry(*layer).or_insert_with(|| Self::create_vm(VMApi::new(*layer, buffer), max_sleep));
+        }
+
+        if !self.vms.contains_key(&self.selected) {
+            if let Some(first) = layers.first() {
+                self.selected = *first;
+            }
+        }
+    }
+
+    fn create_vm(api: VMApi, max_sleep: Option<Duration>) -> vm::VM {
+        let mut vm = vm::VM::new(Box::new(api), Box::new(CLOCK.clone()));
+
+        if let Some(max_sleep) = max_sleep {
+            vm.set_max_sleep(max_sleep);
+        }
+
+        vm
+    }
+}
+
+struct SelectedVM(MutexGuard<'static, Players>);
+
+impl Deref for SelectedVM {
+    type Target = vm::VM;
+
+    fn deref(&self) -> &vm::VM {
+        &self.0.vms[&self.0.selected]
+    }
+}
+
+impl DerefMut for SelectedVM {
+    fn deref_mut(&mut self) -> &mut vm::VM {
+        let selected = self.0.selected;
+        self.0.vms.get_mut(&selected).unwrap()
+    }
+}
+
 fn get_scene() -> MutexGuard<'static, Scene> {
     SCENE.lock().unwrap()
 }
 
-fn get_vm() -> MutexGuard<'static, vm::VM> {
-    VM.lock().unwrap()
+fn get_players() -> MutexGuard<'static, Players> {
+    PLAYERS.lock().unwrap()
 }
 
-struct VMApi;
+fn get_vm() -> SelectedVM {
+    SelectedVM(get_players())
+}
 
-impl vm::ExternalApi for VMApi {
-    fn rand(&self, min: i32, max: i32) -> i32 {
-        let value = Math::random() * (max - min) as f64 + min as f64;
-        value.round() as i32
+fn get_session() -> MutexGuard<'static, Session> {
+    SESSION.lock().unwrap()
+}
+
+// used when the caller does not ask for a specific seed
+fn random_seed() -> u64 {
+    (Math::random() * u32::MAX as f64) as u64
+}
+
+// back to the real inputs and clock, if a trace was being recorded or replayed
+fn end_session() {
+    let mut session = get_session();
+
+    let Some(layer) = session.layer() else {
+        return;
+    };
+
+    // the VM is gone if its layer was removed
+    if let Some(vm) = get_players().vms.get_mut(&layer) {
+        vm.set_io(Box::new(VMApi::active(layer)), Box::new(CLOCK.clone()));
+    }
+
+    *session = Session::Live;
+}
+
+fn find_layer(name: &str) -> Result<usize, JsError> {
+    get_scene()
+        .compositor()
+        .find(name)
+        .ok_or_else(|| JsError::new(&format!("Unknown layer: {}", name)))
+}
+
+// the programs keep running on the layers of the first segment, the others are removed
+// a recording or replay is ended, as its trace would not match the new lights
+fn change_layout(layout: Layout) {
+    end_session();
+
+    let mut players = get_players();
+    let mut scene = get_scene();
+    scene.set_layout(layout);
+    players.sync(&scene);
+}
+
+// if the layer of the session was removed
+fn end_removed_session() {
+    let removed = match get_session().layer() {
+        Some(layer) => !get_players().vms.contains_key(&layer),
+        None => false,
+    };
+
+    if removed {
+        end_session();
+    }
+}
+
+// Gives each VM the lights of its layer, indexes are relative to the start of the segment
+struct VMApi {
+    scene: Arc<Mutex<Scene>>,
+    layer: usize,
+    buffer: usize,
+}
+
+impl VMApi {
+    fn new(layer: usize, buffer: usize) -> Self {
+        Self::with_scene(SCENE.clone(), layer, buffer)
+    }
+
+    // on a scene other than the live one
+    fn with_scene(scene: Arc<Mutex<Scene>>, layer: usize, buffer: usize) -> Self {
+        Self { scene, layer, buffer }
+    }
+
+    fn scene(&self) -> MutexGuard<'_, Scene> {
+        self.scene.lock().unwrap()
     }
 
+    // for the current program of the layer
+    fn active(layer: usize) -> Self {
+        Self::new(layer, get_scene().compositor().layer(layer).active())
+    }
+
+    fn range(&self, scene: &Scene) -> Range<usize> {
+        scene.layer_range(self.layer)
+    }
+}
+
+impl vm::ExternalApi for VMApi {
     fn len(&self) -> usize {
-        Scene::LIGHT_COUNT
+        self.range(&self.scene()).len()
+    }
+
+    fn position(&self, index: usize) -> (i32, i32) {
+        let scene = self.scene();
+        let position = scene.light_position(self.range(&scene).start + index);
+        (position.x, position.y)
+    }
+
+    fn width(&self) -> i32 {
+        self.scene().size().0
+    }
+
+    fn height(&self) -> i32 {
+        self.scene().size().1
+    }
+
+    fn matrix_index(&self, x: usize, y: usize) -> Option<usize> {
+        let scene = self.scene();
+        let range = self.range(&scene);
+
+        // lights of the matrix that belong to another segment are not reachable
+        scene
+            .matrix_index(x, y)
+            .filter(|index| range.contains(index))
+            .map(|index| index - range.start)
     }
 
     fn get(&self, index: usize) -> (u8, u8, u8) {
-        let color = get_scene().get_light_color(index);
+        let color = self.scene().layer_lights(self.layer, self.buffer)[index];
         (color.red(), color.green(), color.blue())
     }
 
     fn set(&self, index: usize, color: (u8, u8, u8)) {
         let color = Color::from_rgb(color.0, color.1, color.2);
-        get_scene().set_light_color(index, color);
+        self.scene().layer_lights_mut(self.layer, self.buffer)[index] = color;
+    }
+
+    fn fade_all(&self, amount: u8) {
+        strip::fade_all(self.scene().layer_lights_mut(self.layer, self.buffer), amount);
+    }
+
+    fn fill(&self, start: usize, end: usize, color: (u8, u8, u8)) {
+        let color = Color::from_rgb(color.0, color.1, color.2);
+        strip::fill(self.scene().layer_lights_mut(self.layer, self.buffer), start, end, color);
+    }
+
+    fn gradient(&self, start: usize, end: usize, color1: (u8, u8, u8), color2: (u8, u8, u8)) {
+        let color1 = Color::from_rgb(color1.0, color1.1, color1.2);
+        let color2 = Color::from_rgb(color2.0, color2.1, color2.2);
+        strip::gradient(self.scene().layer_lights_mut(self.layer, self.buffer), start, end, color1, color2);
+    }
+
+    fn shift(&self, offset: isize) {
+        strip::shift(self.scene().layer_lights_mut(self.layer, self.buffer), offset);
+    }
+
+    fn rotate(&self, offset: isize) {
+        strip::rotate(self.scene().layer_lights_mut(self.layer, self.buffer), offset);
+    }
+
+    fn mirror(&self, start: usize, end: usize) {
+        strip::mirror(self.scene().layer_lights_mut(self.layer, self.buffer), start, end);
+    }
+
+    fn input(&self, channel: u32) -> i32 {
+        INPUTS.lock().unwrap().get(&channel).copied().unwrap_or(0)
     }
 }
 
 #[wasm_bindgen]
-pub fn init() {
+pub fn init(layout: Option<String>) -> Result<(), JsError> {
     console_error_panic_hook::set_once();
     wasm_logger::init(wasm_logger::Config::default());
 
@@ -58,9 +310,188 @@ pub fn init() {
 
     FPS_PRINTER.init();
 
-    // force init
-    let _scene = get_scene();
-    let _vm = get_vm();
+    {
+        // force init
+        let _players = get_players();
+        let _scene = get_scene();
+    }
+
+    if let Some(layout) = layout {
+        set_layout(&layout)?;
+    }
+
+    Ok(())
+}
+
+// the program of the first segment keeps running on the new layout, all lights are turned off
+// JSON list of positions, or document made of points or segments (line, arc, grid)
+#[wasm_bindgen]
+pub fn set_layout(layout: &str) -> Result<(), JsError> {
+    let layout = Layout::from_json(layout).map_err(|e| JsError::from(&*e))?;
+    change_layout(layout);
+
+    Ok(())
+}
+
+// one light per line: x,y
+#[wasm_bindgen]
+pub fn set_layout_csv(layout: &str) -> Result<(), JsError> {
+    let layout = Layout::from_csv(layout).map_err(|e| JsError::from(&*e))?;
+    change_layout(layout);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn set_layout_straight(count: usize) -> Result<(), JsError> {
+    let layout = Layout::straight(count).map_err(|e| JsError::from(&*e))?;
+    change_layout(layout);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn set_layout_circle(count: usize) -> Result<(), JsError> {
+    let layout = Layout::circle(count).map_err(|e| JsError::from(&*e))?;
+    change_layout(layout);
+
+    Ok(())
+}
+
+// wiring: { "start": "bottom-left", "direction": "rows", "serpentine": true }
+#[wasm_bindgen]
+pub fn set_layout_matrix(columns: usize, rows: usize, wiring: &str) -> Result<(), JsError> {
+    let wiring: Wiring = serde_json::from_str(wiring)?;
+    let matrix = Matrix { columns, rows, wiring };
+    let layout = Layout::from_matrix(Position::new(0, 0), 1, matrix).map_err(|e| JsError::from(&*e))?;
+    change_layout(layout);
+
+    Ok(())
+}
+
+// JSON list of { "name", "start", "count" }, all programs are stopped
+// each segment gets one layer, named after it
+#[wasm_bindgen]
+pub fn set_segments(segments: &str) -> Result<(), JsError> {
+    let segments: Vec<Segment> = serde_json::from_str(segments)?;
+
+    // a rejected list leaves everything running
+    get_scene().validate_segments(&segments).map_err(|e| JsError::from(&*e))?;
+    end_session();
+
+    let mut players = get_players();
+    let mut scene = get_scene();
+    scene.set_segments(segments).map_err(|e| JsError::from(&*e))?;
+
+    // every new layer gets a fresh VM, bound to its active buffer
+    players.vms.clear();
+    players.outgoing.clear();
+    players.sync(&scene);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn list_segments() -> Result<String, JsError> {
+    Ok(serde_json::to_string(get_scene().segments())?)
+}
+
+#[derive(serde::Serialize)]
+struct LayerInfo {
+    name: String,
+    segment: String,
+    blend: BlendMode,
+    opacity: u8,
+}
+
+// from the bottom to the top
+#[wasm_bindgen]
+pub fn list_layers() -> Result<String, JsError> {
+    let scene = get_scene();
+    let layers: Vec<LayerInfo> = scene
+        .compositor()
+        .layers()
+        .iter()
+        .map(|layer| LayerInfo {
+            name: layer.name.clone(),
+            segment: scene.segments()[layer.segment].name.clone(),
+            blend: layer.blend,
+            opacity: layer.opacity,
+        })
+        .collect();
+
+    Ok(serde_json::to_string(&layers)?)
+}
+
+// on top of the others, with a stopped program
+#[wasm_bindgen]
+pub fn add_layer(name: &str, segment: &str) -> Result<(), JsError> {
+    let mut players = get_players();
+    let mut scene = get_scene();
+
+    let segment = scene
+        .find_segment(segment)
+        .ok_or_else(|| JsError::new(&format!("Unknown segment: {}", segment)))?;
+    scene.add_layer(name, segment).map_err(|e| JsError::from(&*e))?;
+    players.sync(&scene);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn remove_layer(name: &str) -> Result<(), JsError> {
+    let layer = find_layer(name)?;
+
+    {
+        let mut players = get_players();
+        let mut scene = get_scene();
+
+        if scene.compositor().layers().len() == 1 {
+            return Err(JsError::new("Cannot remove the last layer"));
+        }
+
+        scene.compositor_mut().remove(layer);
+        players.sync(&scene);
+    }
+
+    end_removed_session();
+
+    Ok(())
+}
+
+// JSON list of all layer names, from the bottom to the top
+#[wasm_bindgen]
+pub fn set_layer_order(names: &str) -> Result<(), JsError> {
+    let names: Vec<String> = serde_json::from_str(names)?;
+    get_scene().compositor_mut().set_order(&names).map_err(|e| JsError::from(&*e))
+}
+
+// 0 hides the layer, 255 is fully opaque
+#[wasm_bindgen]
+pub fn set_layer_opacity(name: &str, opacity: u8) -> Result<(), JsError> {
+    let layer = find_layer(name)?;
+    get_scene().compositor_mut().layer_mut(layer).opacity = opacity;
+
+    Ok(())
+}
+
+// normal, add, multiply or max
+#[wasm_bindgen]
+pub fn set_layer_blend(name: &str, blend: &str) -> Result<(), JsError> {
+    let blend: BlendMode = serde_json::from_value(serde_json::Value::String(blend.to_string()))?;
+    let layer = find_layer(name)?;
+    get_scene().compositor_mut().layer_mut(layer).blend = blend;
+
+    Ok(())
+}
+
+// parameters and debugger functions apply to the selected layer
+#[wasm_bindgen]
+pub fn select_layer(name: &str) -> Result<(), JsError> {
+    let layer = find_layer(name)?;
+    get_players().selected = layer;
+
+    Ok(())
 }
 
 #[wasm_bindgen]
@@ -68,75 +499,450 @@ pub fn compile(input: &str) -> Result<String, JsError> {
     compiler::compile(input).map_err(|e| JsError::from(&*e))
 }
 
+// JSON, { "type": "cut" }, { "type": "crossfade", "duration": 1000 } or { "type": "wipe", "duration": 1000, "reverse": false }
+// used by the next calls to execute
+#[wasm_bindgen]
+pub fn set_transition(transition: &str) -> Result<(), JsError> {
+    get_players().transition = serde_json::from_str(transition)?;
+
+    Ok(())
+}
+
+// runs on the given layer (selected one by default), which becomes the selected one
+// each segment starts with a layer of the same name
+// unless the transition is a cut, the previous program keeps running until the transition is over
 #[wasm_bindgen]
-pub fn execute(input: &str) -> Result<(), JsError> {
+pub fn execute(input: &str, seed: Option<u32>, layer: Option<String>) -> Result<(), JsError> {
     let exec = Executable::from_text(input).map_err(|e| JsError::from(&*e))?;
-    get_vm().load_executable(exec);
+    let seed = seed.map_or_else(random_seed, u64::from);
+    let layer = layer.map(|name| find_layer(&name)).transpose()?;
+    end_session();
 
-    get_scene().reset();
+    let mut players = get_players();
+    if let Some(layer) = layer {
+        players.selected = layer;
+    }
+
+    let layer = players.selected;
+    let transition = players.transition;
+    let buffer = get_scene().compositor_mut().layer_mut(layer).start_transition(transition, CLOCK.now());
+
+    if transition == Transition::Cut {
+        players.outgoing.remove(&layer);
+        players.vms.get_mut(&layer).unwrap().load_executable(exec, seed);
+    } else {
+        // breakpoints are not carried over to the new VM
+        let mut vm = Players::create_vm(VMApi::new(layer, buffer), players.max_sleep);
+        vm.load_executable(exec, seed);
+
+        let previous = players.vms.insert(layer, vm).unwrap();
+        players.outgoing.insert(layer, previous);
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn record(input: &str, seed: Option<u32>) -> Result<(), JsError> {
+    let exec = Executable::from_text(input).map_err(|e| JsError::from(&*e))?;
+    let seed = seed.map_or_else(random_seed, u64::from);
+    end_session();
+
+    let mut session = get_session();
+    let mut vm = get_vm();
+    let layer = vm.0.selected;
+
+    let len = get_scene().layer_range(layer).len();
+
+    let recorder = TraceRecorder::new();
+    let api = RecordingApi::new(Box::new(VMApi::active(layer)), recorder.clone());
+    let clock = RecordingClock::new(Box::new(CLOCK.clone()), recorder.clone());
+
+    vm.set_io(Box::new(api), Box::new(clock));
+    vm.load_executable(exec, seed);
+
+    *session = Session::Recording { layer, recorder, executable: input.to_string(), seed, len };
+
+    get_scene().reset_layer(layer);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn stop_recording() -> Result<String, JsError> {
+    let (layer, trace) = match &*get_session() {
+        Session::Recording { layer, recorder, executable, seed, len } => {
+            (*layer, recorder.finish(executable.clone(), *seed, *len))
+        }
+        _ => return Err(JsError::new("Not recording")),
+    };
+
+    end_session();
+    get_scene().reset_layer(layer);
+
+    Ok(serde_json::to_string(&trace)?)
+}
+
+#[wasm_bindgen]
+pub fn replay(trace: &str) -> Result<(), JsError> {
+    let trace: Trace = serde_json::from_str(trace)?;
+    let exec = Executable::from_text(&trace.executable).map_err(|e| JsError::from(&*e))?;
+    end_session();
+
+    let seed = trace.seed;
+    let mut session = get_session();
+    let mut vm = get_vm();
+    let layer = vm.0.selected;
+
+    // the recorded indexes would not fit on fewer lights
+    let len = get_scene().layer_range(layer).len();
+    if trace.len != len {
+        return Err(JsError::new(&format!(
+            "Trace was recorded on {} lights, the layer has {}",
+            trace.len, len
+        )));
+    }
+
+    let replayer = TraceReplayer::new(trace);
+    let api = ReplayApi::new(Box::new(VMApi::active(layer)), replayer.clone());
+    let clock = ReplayClock::new(replayer.clone());
+
+    vm.set_io(Box::new(api), Box::new(clock));
+    vm.load_executable(exec, seed);
+
+    *session = Session::Replaying { layer, replayer };
+
+    get_scene().reset_layer(layer);
 
     Ok(())
 }
 
 #[wasm_bindgen]
 pub fn reset() {
-    get_vm().reset();
+    end_session();
+
+    let mut players = get_players();
+    players.outgoing.clear();
+
+    for vm in players.vms.values_mut() {
+        vm.reset();
+    }
+
     get_scene().reset();
 }
 
+#[wasm_bindgen]
+pub fn list_palettes() -> Result<String, JsError> {
+    let names: Vec<&str> = vm::palettes::PALETTES.iter().map(|palette| palette.name).collect();
+    Ok(serde_json::to_string(&names)?)
+}
+
+#[wasm_bindgen]
+pub fn list_params() -> Result<String, JsError> {
+    let params = get_vm().parameters();
+    Ok(serde_json::to_string(&params)?)
+}
+
+#[wasm_bindgen]
+pub fn set_param(name: &str, value: i32) -> Result<(), JsError> {
+    let session = get_session();
+    let mut vm = get_vm();
+    let layer = vm.0.selected;
+
+    match &*session {
+        Session::Replaying { layer: replayed, .. } if *replayed == layer => {
+            return Err(JsError::new("Parameters are replayed from the trace"));
+        }
+        _ => {}
+    }
+
+    vm.set_parameter(name, value).map_err(|e| JsError::from(&*e))?;
+
+    if let Session::Recording { layer: recorded, recorder, .. } = &*session {
+        if *recorded == layer {
+            recorder.set_parameter(name, value);
+        }
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn set_input(channel: u32, value: i32) {
+    let previous = INPUTS.lock().unwrap().insert(channel, value);
+
+    if previous.unwrap_or(0) != value {
+        let session = get_session();
+        let mut players = get_players();
+        let players = &mut *players;
+
+        for (layer, vm) in players.vms.iter_mut() {
+            match &*session {
+                Session::Recording { layer: recorded, recorder, .. } if recorded == layer => {
+                    recorder.input_changed(channel);
+                    vm.input_changed(channel);
+                }
+                // the replayed program gets the input changes of the trace
+                Session::Replaying { layer: replayed, .. } if replayed == layer => {}
+                _ => vm.input_changed(channel),
+            }
+        }
+
+        for vm in players.outgoing.values_mut() {
+            vm.input_changed(channel);
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn set_max_sleep(max_sleep_ms: u32) {
+    let max_sleep = Duration::from_millis(max_sleep_ms as u64);
+    let mut players = get_players();
+    players.max_sleep = Some(max_sleep);
+
+    let players = &mut *players;
+    for vm in players.vms.values_mut().chain(players.outgoing.values_mut()) {
+        vm.set_max_sleep(max_sleep);
+    }
+}
+
+#[wasm_bindgen]
+pub fn pause() {
+    CLOCK.pause();
+}
+
+#[wasm_bindgen]
+pub fn resume() {
+    CLOCK.resume();
+}
+
+#[wasm_bindgen]
+pub fn set_speed(factor: f64) -> Result<(), JsError> {
+    const MIN_SPEED: f64 = 0.1;
+    const MAX_SPEED: f64 = 10.0;
+
+    if !(MIN_SPEED..=MAX_SPEED).contains(&factor) {
+        return Err(JsError::new(&format!("Speed must be in the range {}-{}", MIN_SPEED, MAX_SPEED)));
+    }
+
+    CLOCK.set_speed(factor);
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn step_frame() {
+    // one frame at 60 FPS
+    const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+    // stepping only makes sense while paused
+    if !CLOCK.paused() {
+        return;
+    }
+
+    CLOCK.advance(FRAME_DURATION);
+    tick_vm();
+}
+
+// runs the program on the current layout and output settings, without touching the live scene
+// as fast as possible: the clock moves by frame_ms after each frame, inputs keep their current values
+// corrected colors, 3 bytes per light (red, green, blue) for each frame
+#[wasm_bindgen]
+pub fn render_offline(input: &str, seed: Option<u32>, frames: u32, frame_ms: u32) -> Result<Vec<u8>, JsError> {
+    let exec = Executable::from_text(input).map_err(|e| JsError::from(&*e))?;
+    let seed = seed.map_or_else(random_seed, u64::from);
+
+    let scene = {
+        let live = get_scene();
+        let mut scene = Scene::with_layout(live.layout().clone());
+        scene.set_calibration(live.calibration().clone()).map_err(|e| JsError::from(&*e))?;
+        scene.set_power_model(live.power_model().clone()).map_err(|e| JsError::from(&*e))?;
+        Arc::new(Mutex::new(scene))
+    };
+
+    let layer = scene.lock().unwrap().compositor().layers()[0].id();
+    let clock = ManualClock::new();
+    let api = VMApi::with_scene(scene.clone(), layer, 0);
+    let mut vm = vm::VM::new(Box::new(api), Box::new(clock.clone()));
+    vm.load_executable(exec, seed);
+
+    let mut output = Vec::new();
+
+    for _ in 0..frames {
+        vm.tick();
+
+        let mut scene = scene.lock().unwrap();
+        scene.compose(clock.now());
+        output.extend(scene.output().iter().flat_map(|color| [color.red(), color.green(), color.blue()]));
+
+        clock.advance(Duration::from_millis(frame_ms as u64));
+    }
+
+    Ok(output)
+}
+
+#[wasm_bindgen]
+pub fn debug_set_breakpoint(address: usize) {
+    get_vm().set_breakpoint(address);
+}
+
+#[wasm_bindgen]
+pub fn debug_remove_breakpoint(address: usize) {
+    get_vm().remove_breakpoint(address);
+}
+
+#[wasm_bindgen]
+pub fn debug_set_block_breakpoint(block_id: &str) {
+    get_vm().set_block_breakpoint(block_id);
+}
+
+#[wasm_bindgen]
+pub fn debug_remove_block_breakpoint(block_id: &str) {
+    get_vm().remove_block_breakpoint(block_id);
+}
+
+#[wasm_bindgen]
+pub fn debug_clear_breakpoints() {
+    get_vm().clear_breakpoints();
+}
+
+#[wasm_bindgen]
+pub fn debug_continue() {
+    get_vm().debug_continue();
+}
+
+#[wasm_bindgen]
+pub fn debug_step_instruction() {
+    get_vm().debug_step_instruction();
+}
+
+#[wasm_bindgen]
+pub fn debug_step_statement() {
+    get_vm().debug_step_statement();
+}
+
+#[wasm_bindgen]
+pub fn debug_state() -> Result<String, JsError> {
+    let state = get_vm().debug_state();
+    Ok(serde_json::to_string(&state)?)
+}
+
+// JSON { "brightness": 0-255, "gamma": [r, g, b], "white-balance": [[rr, rg, rb], [gr, gg, gb], [br, bg, bb]] }
+// missing fields keep the colors, programs still see their uncorrected colors
+#[wasm_bindgen]
+pub fn set_calibration(calibration: &str) -> Result<(), JsError> {
+    let calibration: Calibration = serde_json::from_str(calibration)?;
+    get_scene().set_calibration(calibration).map_err(|e| JsError::from(&*e))
+}
+
+#[wasm_bindgen]
+pub fn get_calibration() -> Result<String, JsError> {
+    Ok(serde_json::to_string(get_scene().calibration())?)
+}
+
+#[wasm_bindgen]
+pub fn set_brightness(brightness: u8) -> Result<(), JsError> {
+    let mut scene = get_scene();
+    let calibration = Calibration { brightness, ..scene.calibration().clone() };
+    scene.set_calibration(calibration).map_err(|e| JsError::from(&*e))
+}
+
+// JSON { "voltage": V, "channel-current": [r, g, b] mA at full value, "idle-current": mA per light, "budget": mA or null }
+// missing fields take WS2812 values, with a budget the output is dimmed to stay under it
+#[wasm_bindgen]
+pub fn set_power_model(model: &str) -> Result<(), JsError> {
+    let model: PowerModel = serde_json::from_str(model)?;
+    get_scene().set_power_model(model).map_err(|e| JsError::from(&*e))
+}
+
+#[wasm_bindgen]
+pub fn get_power_model() -> Result<String, JsError> {
+    Ok(serde_json::to_string(get_scene().power_model())?)
+}
+
+// JSON { "current", "power", "peak-current", "peak-power", "requested-current", "limited" }, in mA and W
+#[wasm_bindgen]
+pub fn power_report() -> Result<String, JsError> {
+    Ok(serde_json::to_string(get_scene().power_report())?)
+}
+
+#[wasm_bindgen]
+pub fn reset_power_peak() {
+    get_scene().reset_power_peak();
+}
+
+// corrected colors of the last frame, 3 bytes per light (red, green, blue) in wiring order
+#[wasm_bindgen]
+pub fn export_frame() -> Vec<u8> {
+    get_scene()
+        .output()
+        .iter()
+        .flat_map(|color| [color.red(), color.green(), color.blue()])
+        .collect()
+}
+
 #[wasm_bindgen]
 pub fn running() -> bool {
-    get_vm().running()
+    get_players().vms.values().any(|vm| vm.running())
 }
 
 #[wasm_bindgen]
 pub fn render() -> Uint8ClampedArray {
     FPS_PRINTER.tick();
 
-    if get_vm().running() {
-        get_vm().tick();
-
-        if !get_vm().running() {
-            // reset scene when program stops
-            get_scene().reset();
-        }
+    if !CLOCK.paused() {
+        tick_vm();
     }
 
-    // do_scene(scene);
-    get_scene().render();
+    let mut scene = get_scene();
+    scene.compose(CLOCK.now());
+    scene.render();
     
     unsafe { 
         Uint8ClampedArray::view(render::frame::raw_buffer())
     }
 }
-/*
-fn do_scene(scene: &mut Scene) {
-    const BLUE: Color = Color::from_rgb(0, 0, 255);
-
-    static mut LAST_TIME: wasm_timer::SystemTime = wasm_timer::SystemTime::UNIX_EPOCH;
-    static mut LAST_INDEX: usize = 0;
-    static mut INITIALIZED: bool = false;
 
-    let last_time = unsafe { &mut LAST_TIME };
-    let last_index = unsafe { &mut LAST_INDEX };
-    let initialized = unsafe { &mut INITIALIZED };
+fn tick_vm() {
+    let session = get_session();
+    let mut players = get_players();
 
-    if !*initialized {
-        *initialized = true;
+    {
+        let mut scene = get_scene();
+        scene.compositor_mut().end_transitions(CLOCK.now());
+        players.sync(&scene);
+    }
 
-        for i in 0..Scene::LIGHT_COUNT {
-            scene.set_light_color(i, BLUE);
+    // previous programs only draw into their own buffer
+    for vm in players.outgoing.values_mut() {
+        if vm.running() {
+            vm.tick();
         }
     }
 
-    let now = wasm_timer::SystemTime::now();
-    if now > last_time.checked_add(Duration::from_millis(20)).unwrap() {
-        *last_time = now;
+    for (&layer, vm) in players.vms.iter_mut() {
+        if !vm.running() {
+            continue;
+        }
+
+        match &*session {
+            Session::Recording { layer: recorded, recorder, .. } if *recorded == layer => recorder.tick(),
+            Session::Replaying { layer: replayed, replayer } if *replayed == layer => {
+                let more = replayer.tick(vm);
+
+                if !more {
+                    // end of the trace
+                    vm.reset();
+                }
+            }
+            _ => {}
+        }
 
-       
-        scene.set_light_color(*last_index, BLUE);
-        *last_index = (*last_index + 1) % Scene::LIGHT_COUNT;
-        scene.set_light_color(*last_index, Color::WHITE);
+        vm.tick();
+
+        if !vm.running() {
+            // reset layer when program stops
+            get_scene().reset_layer(layer);
+        }
     }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,320 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    transition::{RunningTransition, Transition},
+    Color, Segment,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlendMode {
+    // the layer covers what is below, opacity is its alpha
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    Max,
+}
+
+impl BlendMode {
+    pub fn apply(&self, below: &Color, layer: &Color, opacity: u8) -> Color {
+        match self {
+            BlendMode::Normal => below.blend(layer, opacity),
+            BlendMode::Add => below.add_saturating(&layer.scale(opacity)),
+            BlendMode::Multiply => below.blend(&below.multiply(layer), opacity),
+            BlendMode::Max => below.blend(&below.max(layer), opacity),
+        }
+    }
+}
+
+// Lights of one program, drawn over a segment
+// During a transition, the previous program keeps running in the other buffer
+pub struct Layer {
+    id: usize,
+    pub name: String,
+    pub segment: usize,
+    pub blend: BlendMode,
+    pub opacity: u8,
+    buffers: [Vec<Color>; 2],
+    active: usize,
+    transition: Option<RunningTransition>,
+}
+
+impl Layer {
+    // stays the same when layers are added, removed or moved
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // buffer of the current program
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    pub fn buffer(&self, buffer: usize) -> &[Color] {
+        &self.buffers[buffer]
+    }
+
+    pub fn buffer_mut(&mut self, buffer: usize) -> &mut [Color] {
+        &mut self.buffers[buffer]
+    }
+
+    pub fn in_transition(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    // for a new program, returns the buffer it must draw into
+    pub fn start_transition(&mut self, transition: Transition, now: Duration) -> usize {
+        if transition == Transition::Cut {
+            self.reset_active();
+            return self.active;
+        }
+
+        // an ongoing transition is cut, its previous program is dropped
+        let from = self.active;
+        self.active = 1 - from;
+        self.buffers[self.active].fill(Color::BLACK);
+        self.transition = Some(RunningTransition::new(transition, from, now));
+
+        self.active
+    }
+
+    pub fn end_transition(&mut self, now: Duration) {
+        if self.transition.as_ref().is_some_and(|transition| transition.finished(now)) {
+            self.transition = None;
+        }
+    }
+
+    // also cuts an ongoing transition
+    pub fn reset_active(&mut self) {
+        self.transition = None;
+        self.buffers[self.active].fill(Color::BLACK);
+    }
+
+    pub fn reset(&mut self) {
+        self.transition = None;
+
+        for buffer in self.buffers.iter_mut() {
+            buffer.fill(Color::BLACK);
+        }
+    }
+
+    // lights are turned off
+    pub fn resize(&mut self, len: usize) {
+        self.transition = None;
+        self.buffers = [vec![Color::BLACK; len], vec![Color::BLACK; len]];
+    }
+
+    // calls f with the index and color of each light, both programs are mixed during a transition
+    fn for_each_light(&self, now: Duration, mut f: impl FnMut(usize, Color)) {
+        match &self.transition {
+            Some(transition) => {
+                let progress = transition.progress(now);
+
+                for index in 0..self.buffers[self.active].len() {
+                    f(index, transition.mix(&self.buffers, index, progress));
+                }
+            }
+            None => {
+                for (index, light) in self.buffers[self.active].iter().enumerate() {
+                    f(index, *light);
+                }
+            }
+        }
+    }
+}
+
+// Combines layers, from the bottom to the top, into the lights of the scene
+pub struct Compositor {
+    layers: Vec<Layer>,
+    next_id: usize,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Self {
+            layers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    // one layer per segment, named after it
+    pub fn with_segments(segments: &[Segment]) -> Self {
+        let mut compositor = Self::new();
+        compositor.set_segments(segments);
+        compositor
+    }
+
+    // replaces all layers by one per segment, new ids are never reused so that nothing bound to an old layer
+    // (eg: a running program) can end up on a new one
+    pub fn set_segments(&mut self, segments: &[Segment]) {
+        self.layers.clear();
+
+        for (index, segment) in segments.iter().enumerate() {
+            self.add(&segment.name, index, segment.count).unwrap();
+        }
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [Layer] {
+        &mut self.layers
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.layers.iter().find(|layer| layer.name == name).map(Layer::id)
+    }
+
+    pub fn layer(&self, id: usize) -> &Layer {
+        self.layers.iter().find(|layer| layer.id == id).expect("unknown layer")
+    }
+
+    pub fn layer_mut(&mut self, id: usize) -> &mut Layer {
+        self.layers.iter_mut().find(|layer| layer.id == id).expect("unknown layer")
+    }
+
+    // on top of the others, fully opaque
+    pub fn add(&mut self, name: &str, segment: usize, len: usize) -> Result<usize> {
+        if name.is_empty() {
+            anyhow::bail!("Layer name must not be empty");
+        }
+
+        if self.find(name).is_some() {
+            anyhow::bail!("Duplicate layer name: {}", name);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.layers.push(Layer {
+            id,
+            name: name.to_string(),
+            segment,
+            blend: BlendMode::Normal,
+            opacity: 255,
+            buffers: [vec![Color::BLACK; len], vec![Color::BLACK; len]],
+            active: 0,
+            transition: None,
+        });
+
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        self.layers.retain(|layer| layer.id != id);
+    }
+
+    // keeps only the layers for which f returns true
+    pub fn retain(&mut self, f: impl FnMut(&Layer) -> bool) {
+        self.layers.retain(f);
+    }
+
+    // names from the bottom to the top, all layers must be listed once
+    pub fn set_order(&mut self, names: &[String]) -> Result<()> {
+        if names.len() != self.layers.len() {
+            anyhow::bail!("Layer order must list all {} layers", self.layers.len());
+        }
+
+        let mut order = Vec::with_capacity(names.len());
+
+        for name in names {
+            match self.layers.iter().position(|layer| &layer.name == name) {
+                Some(index) if !order.contains(&index) => order.push(index),
+                _ => anyhow::bail!("Unknown or repeated layer: {}", name),
+            }
+        }
+
+        let mut layers: Vec<Option<Layer>> = self.layers.drain(..).map(Some).collect();
+        self.layers = order.into_iter().map(|index| layers[index].take().unwrap()).collect();
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.reset();
+        }
+    }
+
+    pub fn end_transitions(&mut self, now: Duration) {
+        for layer in self.layers.iter_mut() {
+            layer.end_transition(now);
+        }
+    }
+
+    pub fn compose(&self, segments: &[Segment], output: &mut [Color], now: Duration) {
+        output.fill(Color::BLACK);
+
+        for layer in self.layers.iter() {
+            let output = &mut output[segments[layer.segment].range()];
+
+            layer.for_each_light(now, |index, light| {
+                output[index] = layer.blend.apply(&output[index], &light, layer.opacity);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELOW: Color = Color::from_rgb(100, 200, 50);
+    const LAYER: Color = Color::from_rgb(200, 100, 0);
+
+    #[test]
+    fn blend_modes() {
+        let modes = [
+            (BlendMode::Normal, 0xC86400),
+            (BlendMode::Add, 0xFFFF32),
+            (BlendMode::Multiply, 0x4E4E00),
+            (BlendMode::Max, 0xC8C832),
+        ];
+
+        for (mode, expected) in modes {
+            assert_eq!(mode.apply(&BELOW, &LAYER, 0).packed(), BELOW.packed(), "{:?}", mode);
+            assert_eq!(mode.apply(&BELOW, &LAYER, 255).packed(), expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn set_segments_does_not_reuse_ids() {
+        let segments = [
+            Segment { name: "a".to_string(), start: 0, count: 2 },
+            Segment { name: "b".to_string(), start: 2, count: 3 },
+        ];
+
+        let mut compositor = Compositor::with_segments(&segments);
+        let ids: Vec<usize> = compositor.layers().iter().map(Layer::id).collect();
+
+        compositor.set_segments(&segments);
+        assert_eq!(compositor.layers().len(), 2);
+        assert!(compositor.layers().iter().all(|layer| !ids.contains(&layer.id())));
+        assert_eq!(compositor.layer(compositor.find("b").unwrap()).buffer(0).len(), 3);
+    }
+
+    #[test]
+    fn compose_transition() {
+        let segments = [Segment { name: "a".to_string(), start: 1, count: 2 }];
+        let mut compositor = Compositor::with_segments(&segments);
+        let layer = compositor.layer_mut(0);
+        layer.buffer_mut(0).fill(Color::from_rgb(255, 0, 0));
+
+        let buffer = layer.start_transition(Transition::Crossfade { duration: 1000 }, Duration::ZERO);
+        layer.buffer_mut(buffer).fill(Color::from_rgb(0, 0, 255));
+
+        let mut output = [Color::WHITE; 4];
+        compositor.compose(&segments, &mut output, Duration::from_millis(500));
+        assert_eq!(output.map(|light| light.packed()), [0, 0x80007F, 0x80007F, 0]);
+
+        compositor.end_transitions(Duration::from_secs(1));
+        assert!(!compositor.layer(0).in_transition());
+        compositor.compose(&segments, &mut output, Duration::from_secs(1));
+        assert_eq!(output.map(|light| light.packed()), [0, 0x0000FF, 0x0000FF, 0]);
+    }
+}
@@ -29,6 +29,77 @@ impl Color {
     pub const fn blue(&self) -> u8 {
         self.b
     }
+
+    // 0xRRGGBB, alpha is not stored
+    pub const fn from_packed(value: u32) -> Self {
+        Self::from_rgb((value >> 16) as u8, (value >> 8) as u8, value as u8)
+    }
+
+    pub const fn packed(&self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    // hue in degrees (0-359), saturation and value in 0-255
+    pub fn from_hsv(hue: u16, saturation: u8, value: u8) -> Self {
+        let chroma = value as u32 * saturation as u32 / 255;
+        Self::from_chroma(hue, chroma, value as u32 - chroma)
+    }
+
+    // hue in degrees (0-359), saturation and lightness in 0-255
+    pub fn from_hsl(hue: u16, saturation: u8, lightness: u8) -> Self {
+        let lightness = lightness as u32;
+        let chroma = (255 - (2 * lightness as i32 - 255).unsigned_abs()) * saturation as u32 / 255;
+        Self::from_chroma(hue, chroma, lightness - chroma / 2)
+    }
+
+    // amount 0 gives self, 255 gives other
+    pub fn blend(&self, other: &Color, amount: u8) -> Self {
+        let mix = |a: u8, b: u8| (a as i32 + (b as i32 - a as i32) * amount as i32 / 255) as u8;
+        Self::from_rgb(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
+
+    // amount 255 keeps the color, 0 gives black
+    pub fn scale(&self, amount: u8) -> Self {
+        let scale = |c: u8| (c as u16 * amount as u16 / 255) as u8;
+        Self::from_rgb(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    pub fn add_saturating(&self, other: &Color) -> Self {
+        Self::from_rgb(
+            self.r.saturating_add(other.r),
+            self.g.saturating_add(other.g),
+            self.b.saturating_add(other.b),
+        )
+    }
+
+    // per channel, white keeps the color, black gives black
+    pub fn multiply(&self, other: &Color) -> Self {
+        let multiply = |a: u8, b: u8| (a as u16 * b as u16 / 255) as u8;
+        Self::from_rgb(multiply(self.r, other.r), multiply(self.g, other.g), multiply(self.b, other.b))
+    }
+
+    // per channel
+    pub fn max(&self, other: &Color) -> Self {
+        Self::from_rgb(self.r.max(other.r), self.g.max(other.g), self.b.max(other.b))
+    }
+
+    fn from_chroma(hue: u16, chroma: u32, min: u32) -> Self {
+        let hue = hue as u32 % 360;
+
+        // second largest component, goes up and down across each 60° sector
+        let x = chroma * (60 - ((hue % 120) as i32 - 60).unsigned_abs()) / 60;
+
+        let (r, g, b) = match hue / 60 {
+            0 => (chroma, x, 0),
+            1 => (x, chroma, 0),
+            2 => (0, chroma, x),
+            3 => (0, x, chroma),
+            4 => (x, 0, chroma),
+            _ => (chroma, 0, x),
+        };
+
+        Self::from_rgb((r + min) as u8, (g + min) as u8, (b + min) as u8)
+    }
 }
 
 #[repr(C)]
@@ -0,0 +1,188 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use super::Position;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    // the wire goes along a row, then the next one
+    #[default]
+    Rows,
+    Columns,
+}
+
+// How the wire goes through a matrix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Wiring {
+    pub start: Corner,
+    pub direction: Direction,
+    // every other line goes backward, instead of each line starting on the same side
+    pub serpentine: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Matrix {
+    pub columns: usize,
+    pub rows: usize,
+    pub wiring: Wiring,
+}
+
+impl Matrix {
+    // column 0 is on the left, row 0 is on the top
+    pub fn index(&self, column: usize, row: usize) -> usize {
+        // move the start corner to (0, 0)
+        let column = match self.wiring.start {
+            Corner::TopRight | Corner::BottomRight => self.columns - column - 1,
+            Corner::TopLeft | Corner::BottomLeft => column,
+        };
+
+        let row = match self.wiring.start {
+            Corner::BottomLeft | Corner::BottomRight => self.rows - row - 1,
+            Corner::TopLeft | Corner::TopRight => row,
+        };
+
+        let (line, offset, line_len) = match self.wiring.direction {
+            Direction::Rows => (row, column, self.columns),
+            Direction::Columns => (column, row, self.rows),
+        };
+
+        let offset = if self.wiring.serpentine && line % 2 == 1 {
+            line_len - offset - 1
+        } else {
+            offset
+        };
+
+        line * line_len + offset
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.saturating_mul(self.rows)
+    }
+}
+
+// lights along the x axis, starting at (0, 0)
+pub fn straight(count: usize, spacing: i32) -> Vec<Position> {
+    let end = Position::new(spacing * (count.max(1) - 1) as i32, 0);
+    line(Position::new(0, 0), end, count)
+}
+
+// evenly spread, both ends included
+pub fn line(start: Position, end: Position, count: usize) -> Vec<Position> {
+    if count == 1 {
+        return vec![start];
+    }
+
+    let steps = (count - 1) as f64;
+
+    (0..count)
+        .map(|index| {
+            let ratio = index as f64 / steps;
+            Position::new(
+                lerp(start.x, end.x, ratio),
+                lerp(start.y, end.y, ratio),
+            )
+        })
+        .collect()
+}
+
+// angles are in degrees, clockwise from the right (as y grows downward)
+pub fn arc(center: Position, radius: i32, start_angle: f64, end_angle: f64, count: usize) -> Vec<Position> {
+    let sweep = end_angle - start_angle;
+
+    // on a closed circle the last light must not be on top of the first one
+    let steps = if sweep.abs() >= 360.0 || count == 1 {
+        count as f64
+    } else {
+        (count - 1) as f64
+    };
+
+    (0..count)
+        .map(|index| {
+            let angle = (start_angle + sweep * index as f64 / steps) * PI / 180.0;
+            Position::new(
+                center.x + (radius as f64 * angle.cos()).round() as i32,
+                center.y + (radius as f64 * angle.sin()).round() as i32,
+            )
+        })
+        .collect()
+}
+
+pub fn circle(center: Position, radius: i32, count: usize) -> Vec<Position> {
+    arc(center, radius, 0.0, 360.0, count)
+}
+
+// origin is the top left light
+pub fn matrix(origin: Position, spacing: i32, matrix: &Matrix) -> Vec<Position> {
+    let mut positions = vec![origin; matrix.len()];
+
+    for row in 0..matrix.rows {
+        for column in 0..matrix.columns {
+            positions[matrix.index(column, row)] = Position::new(
+                origin.x + column as i32 * spacing,
+                origin.y + row as i32 * spacing,
+            );
+        }
+    }
+
+    positions
+}
+
+fn lerp(start: i32, end: i32, ratio: f64) -> i32 {
+    (start as f64 + (end - start) as f64 * ratio).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // index of each light of a 3x2 matrix, row by row
+    fn indexes(start: Corner, direction: Direction, serpentine: bool) -> [[usize; 3]; 2] {
+        let matrix = Matrix { columns: 3, rows: 2, wiring: Wiring { start, direction, serpentine } };
+        [0, 1].map(|row| [0, 1, 2].map(|column| matrix.index(column, row)))
+    }
+
+    #[test]
+    fn rows() {
+        assert_eq!(indexes(Corner::TopLeft, Direction::Rows, false), [[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(indexes(Corner::TopRight, Direction::Rows, false), [[2, 1, 0], [5, 4, 3]]);
+        assert_eq!(indexes(Corner::BottomLeft, Direction::Rows, false), [[3, 4, 5], [0, 1, 2]]);
+        assert_eq!(indexes(Corner::BottomRight, Direction::Rows, false), [[5, 4, 3], [2, 1, 0]]);
+    }
+
+    #[test]
+    fn rows_serpentine() {
+        assert_eq!(indexes(Corner::TopLeft, Direction::Rows, true), [[0, 1, 2], [5, 4, 3]]);
+        assert_eq!(indexes(Corner::TopRight, Direction::Rows, true), [[2, 1, 0], [3, 4, 5]]);
+        assert_eq!(indexes(Corner::BottomLeft, Direction::Rows, true), [[5, 4, 3], [0, 1, 2]]);
+        assert_eq!(indexes(Corner::BottomRight, Direction::Rows, true), [[3, 4, 5], [2, 1, 0]]);
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(indexes(Corner::TopLeft, Direction::Columns, false), [[0, 2, 4], [1, 3, 5]]);
+        assert_eq!(indexes(Corner::TopRight, Direction::Columns, false), [[4, 2, 0], [5, 3, 1]]);
+        assert_eq!(indexes(Corner::BottomLeft, Direction::Columns, false), [[1, 3, 5], [0, 2, 4]]);
+        assert_eq!(indexes(Corner::BottomRight, Direction::Columns, false), [[5, 3, 1], [4, 2, 0]]);
+    }
+
+    #[test]
+    fn columns_serpentine() {
+        assert_eq!(indexes(Corner::TopLeft, Direction::Columns, true), [[0, 3, 4], [1, 2, 5]]);
+        assert_eq!(indexes(Corner::TopRight, Direction::Columns, true), [[4, 3, 0], [5, 2, 1]]);
+        assert_eq!(indexes(Corner::BottomLeft, Direction::Columns, true), [[1, 2, 5], [0, 3, 4]]);
+        assert_eq!(indexes(Corner::BottomRight, Direction::Columns, true), [[5, 2, 1], [4, 3, 0]]);
+    }
+}
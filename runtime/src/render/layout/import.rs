@@ -0,0 +1,343 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::{
+    generators::{self, Matrix, Wiring},
+    Layout, Position,
+};
+
+// Either a plain list of positions, or a document:
+// { "points": [{ "x": 0, "y": 0 }, ...] }
+// { "segments": [{ "type": "line", ... }, { "type": "arc", ... }, { "type": "grid", ... }] }
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    points: Option<Vec<Position>>,
+    segments: Option<Vec<Segment>>,
+}
+
+// Segments follow each other on the wire
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Segment {
+    Points {
+        points: Vec<Position>,
+    },
+    Line {
+        start: Position,
+        end: Position,
+        count: usize,
+    },
+    #[serde(rename_all = "kebab-case")]
+    Arc {
+        center: Position,
+        radius: i32,
+        #[serde(default)]
+        start_angle: f64,
+        #[serde(default = "full_turn")]
+        end_angle: f64,
+        count: usize,
+    },
+    Grid {
+        #[serde(default)]
+        origin: Position,
+        columns: usize,
+        rows: usize,
+        #[serde(default = "unit_spacing")]
+        spacing: i32,
+        #[serde(default)]
+        wiring: Wiring,
+    },
+}
+
+fn full_turn() -> f64 {
+    360.0
+}
+
+fn unit_spacing() -> i32 {
+    1
+}
+
+pub fn parse_json(input: &str) -> Result<Layout> {
+    if input.trim_start().starts_with('[') {
+        let positions: Vec<Position> =
+            serde_json::from_str(input).map_err(|e| anyhow::anyhow!("Invalid layout: {}", e))?;
+        return Layout::new(positions);
+    }
+
+    let document: Document = serde_json::from_str(input).map_err(|e| anyhow::anyhow!("Invalid layout: {}", e))?;
+
+    match (document.points, document.segments) {
+        (Some(points), None) => Layout::new(points),
+        (None, Some(segments)) => from_segments(segments),
+        _ => anyhow::bail!("Layout must have either points or segments"),
+    }
+}
+
+// one light per line: x,y (other columns are ignored, decimals are rounded)
+// empty lines, lines starting with # and a header line are skipped
+pub fn parse_csv(input: &str) -> Result<Layout> {
+    let mut positions = Vec::new();
+    let mut header_allowed = true;
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() < 2 {
+            anyhow::bail!("Line {}: expected x,y", number + 1);
+        }
+
+        let (x, y) = match (parse_coordinate(fields[0]), parse_coordinate(fields[1])) {
+            (Some(x), Some(y)) => (x, y),
+            _ if header_allowed => {
+                header_allowed = false;
+                continue;
+            }
+            _ => anyhow::bail!("Line {}: invalid coordinates '{}'", number + 1, line),
+        };
+
+        header_allowed = false;
+        positions.push(Position::new(x, y));
+    }
+
+    Layout::new(positions)
+}
+
+fn parse_coordinate(value: &str) -> Option<i32> {
+    let value: f64 = value.parse().ok()?;
+
+    if !value.is_finite() || value < i32::MIN as f64 || value > i32::MAX as f64 {
+        return None;
+    }
+
+    Some(value.round() as i32)
+}
+
+fn from_segments(segments: Vec<Segment>) -> Result<Layout> {
+    if segments.is_empty() {
+        anyhow::bail!("Layout must have at least one segment");
+    }
+
+    // a layout made of a single grid can be addressed by coordinates
+    if let [Segment::Grid { origin, columns, rows, spacing, wiring }] = segments.as_slice() {
+        validate_grid(*columns, *rows)
+            .map_err(|e| anyhow::anyhow!("Segment 1: {}", e))?;
+
+        let matrix = Matrix { columns: *columns, rows: *rows, wiring: *wiring };
+        return Layout::from_matrix(*origin, *spacing, matrix).map_err(|e| anyhow::anyhow!("Segment 1: {}", e));
+    }
+
+    let mut positions = Vec::new();
+
+    for (index, segment) in segments.into_iter().enumerate() {
+        let segment_positions = generate(segment)
+            .map_err(|e| anyhow::anyhow!("Segment {}: {}", index + 1, e))?;
+
+        positions.extend(segment_positions);
+
+        // fail early on huge counts, before generating everything
+        if positions.len() > Layout::MAX_LIGHTS {
+            anyhow::bail!("Layout has more than {} lights", Layout::MAX_LIGHTS);
+        }
+    }
+
+    Layout::new(positions)
+}
+
+fn generate(segment: Segment) -> Result<Vec<Position>> {
+    match segment {
+        Segment::Points { points } => {
+            if points.is_empty() {
+                anyhow::bail!("Points must not be empty");
+            }
+
+            Ok(points)
+        }
+        Segment::Line { start, end, count } => {
+            validate_count(count)?;
+            Layout::validate_position(start)?;
+            Layout::validate_position(end)?;
+
+            Ok(generators::line(start, end, count))
+        }
+        Segment::Arc { center, radius, start_angle, end_angle, count } => {
+            validate_count(count)?;
+            Layout::validate_position(center)?;
+
+            if radius <= 0 || radius > Layout::MAX_COORDINATE {
+                anyhow::bail!("Radius must be in the range 1-{}", Layout::MAX_COORDINATE);
+            }
+
+            if !start_angle.is_finite() || !end_angle.is_finite() {
+                anyhow::bail!("Angles must be finite numbers");
+            }
+
+            Ok(generators::arc(center, radius, start_angle, end_angle, count))
+        }
+        Segment::Grid { origin, columns, rows, spacing, wiring } => {
+            validate_grid(columns, rows)?;
+            Layout::validate_position(origin)?;
+            Layout::validate_spacing(spacing)?;
+
+            let matrix = Matrix { columns, rows, wiring };
+            Ok(generators::matrix(origin, spacing, &matrix))
+        }
+    }
+}
+
+fn validate_count(count: usize) -> Result<()> {
+    if count == 0 || count > Layout::MAX_LIGHTS {
+        anyhow::bail!("Count must be in the range 1-{}", Layout::MAX_LIGHTS);
+    }
+
+    Ok(())
+}
+
+fn validate_grid(columns: usize, rows: usize) -> Result<()> {
+    if columns == 0 || rows == 0 {
+        anyhow::bail!("Grid must have at least one column and one row");
+    }
+
+    if columns.saturating_mul(rows) > Layout::MAX_LIGHTS {
+        anyhow::bail!("Grid has more than {} lights", Layout::MAX_LIGHTS);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(layout: &Layout) -> Vec<(i32, i32)> {
+        layout.positions().iter().map(|position| (position.x, position.y)).collect()
+    }
+
+    fn json_error(input: &str) -> String {
+        parse_json(input).unwrap_err().to_string()
+    }
+
+    fn csv_error(input: &str) -> String {
+        parse_csv(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn json_positions() {
+        let layout = parse_json(r#"[{ "x": 1, "y": 2 }, { "x": -3, "y": 4 }]"#).unwrap();
+        assert_eq!(positions(&layout), vec![(1, 2), (-3, 4)]);
+        assert!(layout.matrix().is_none());
+
+        let layout = parse_json(r#"{ "points": [{ "x": 5, "y": 6 }] }"#).unwrap();
+        assert_eq!(positions(&layout), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn json_segments() {
+        let layout = parse_json(
+            r#"{ "segments": [
+                { "type": "line", "start": { "x": 0, "y": 0 }, "end": { "x": 10, "y": 0 }, "count": 3 },
+                { "type": "arc", "center": { "x": 0, "y": 0 }, "radius": 10, "end-angle": 180, "count": 3 },
+                { "type": "grid", "origin": { "x": 100, "y": 100 }, "columns": 2, "rows": 1, "spacing": 5 }
+            ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            positions(&layout),
+            vec![(0, 0), (5, 0), (10, 0), (10, 0), (0, 10), (-10, 0), (100, 100), (105, 100)]
+        );
+        assert!(layout.matrix().is_none());
+    }
+
+    #[test]
+    fn json_single_grid_is_a_matrix() {
+        let layout = parse_json(
+            r#"{ "segments": [{ "type": "grid", "columns": 2, "rows": 2,
+                "wiring": { "start": "bottom-left", "serpentine": true } }] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(positions(&layout), vec![(0, 1), (1, 1), (1, 0), (0, 0)]);
+        assert_eq!(layout.matrix().unwrap().index(0, 0), 3);
+    }
+
+    #[test]
+    fn json_errors() {
+        assert_eq!(json_error(r#"{}"#), "Layout must have either points or segments");
+        assert_eq!(json_error(r#"{ "points": [], "segments": [] }"#), "Layout must have either points or segments");
+        assert_eq!(json_error(r#"{ "segments": [] }"#), "Layout must have at least one segment");
+        assert_eq!(json_error(r#"[]"#), "Layout must have at least one light");
+        assert!(json_error(r#"{ "lights": [] }"#).starts_with("Invalid layout: unknown field `lights`"));
+        assert!(json_error(r#"[{ "x": 1 }]"#).starts_with("Invalid layout: missing field `y`"));
+
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "points", "points": [{ "x": 0, "y": 0 }] },
+                { "type": "line", "start": { "x": 0, "y": 0 }, "end": { "x": 1, "y": 0 }, "count": 0 }] }"#),
+            "Segment 2: Count must be in the range 1-4096"
+        );
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "arc", "center": { "x": 0, "y": 0 }, "radius": 0, "count": 1 }] }"#),
+            "Segment 1: Radius must be in the range 1-16777216"
+        );
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "grid", "columns": 0, "rows": 2 }] }"#),
+            "Segment 1: Grid must have at least one column and one row"
+        );
+    }
+
+    #[test]
+    fn json_rejects_bad_spacing() {
+        for spacing in [0, -1, 4097] {
+            let grid = format!(r#"{{ "type": "grid", "columns": 2, "rows": 2, "spacing": {} }}"#, spacing);
+
+            let single = format!(r#"{{ "segments": [{}] }}"#, grid);
+            assert_eq!(json_error(&single), "Segment 1: Spacing must be in the range 1-4096");
+
+            let several = format!(r#"{{ "segments": [{}, {}] }}"#, grid, grid);
+            assert_eq!(json_error(&several), "Segment 1: Spacing must be in the range 1-4096");
+        }
+    }
+
+    #[test]
+    fn json_rejects_huge_coordinates() {
+        assert_eq!(
+            json_error(r#"[{ "x": 0, "y": 0 }, { "x": 2147483647, "y": 0 }]"#),
+            "Light 2: Coordinates (2147483647, 0) must be within ±16777216"
+        );
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "line", "start": { "x": -2147483648, "y": 0 },
+                "end": { "x": 2147483647, "y": 0 }, "count": 2 }] }"#),
+            "Segment 1: Coordinates (-2147483648, 0) must be within ±16777216"
+        );
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "arc", "center": { "x": 16777216, "y": 0 },
+                "radius": 16777216, "count": 1 }] }"#),
+            "Light 1: Coordinates (33554432, 0) must be within ±16777216"
+        );
+        assert_eq!(
+            json_error(r#"{ "segments": [{ "type": "grid", "origin": { "x": 16777216, "y": 0 },
+                "columns": 2, "rows": 1 }] }"#),
+            "Segment 1: Light 2: Coordinates (16777217, 0) must be within ±16777216"
+        );
+    }
+
+    #[test]
+    fn csv_positions() {
+        let layout = parse_csv("x,y\n# comment\n\n1,2\n 3.6 , -4.4 ,ignored\n").unwrap();
+        assert_eq!(positions(&layout), vec![(1, 2), (4, -4)]);
+    }
+
+    #[test]
+    fn csv_errors() {
+        assert_eq!(csv_error(""), "Layout must have at least one light");
+        assert_eq!(csv_error("1,2\n3"), "Line 2: expected x,y");
+        assert_eq!(csv_error("1,2\nx,y"), "Line 2: invalid coordinates 'x,y'");
+        assert_eq!(csv_error("x,y\nx,y"), "Line 2: invalid coordinates 'x,y'");
+        assert_eq!(csv_error("0,0\n1e10,0"), "Line 2: invalid coordinates '1e10,0'");
+        assert_eq!(csv_error("0,0\n20000000,0"), "Light 2: Coordinates (20000000, 0) must be within ±16777216");
+    }
+}
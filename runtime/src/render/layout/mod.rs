@@ -0,0 +1,160 @@
+mod generators;
+mod import;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+pub use generators::{Corner, Direction, Matrix, Wiring};
+
+// Position of a light in the installation, in any unit (the renderer scales the layout to fit the screen).
+// y grows downward, as on screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Position {
+    pub const fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+}
+
+// Lights in wiring order
+#[derive(Debug, Clone)]
+pub struct Layout {
+    positions: Vec<Position>,
+    // set when the lights form a single matrix
+    matrix: Option<Matrix>,
+    // (top left, bottom right)
+    bounds: (Position, Position),
+}
+
+impl Layout {
+    pub const MAX_LIGHTS: usize = 4096;
+    // keeps the arithmetic on coordinates (generators, sizes) far from overflowing
+    pub const MAX_COORDINATE: i32 = 1 << 24;
+    pub const MAX_SPACING: i32 = Self::MAX_COORDINATE / Self::MAX_LIGHTS as i32;
+
+    pub fn new(positions: Vec<Position>) -> Result<Self> {
+        Self::validate_len(positions.len())?;
+
+        for (index, position) in positions.iter().enumerate() {
+            Self::validate_position(*position).map_err(|e| anyhow::anyhow!("Light {}: {}", index + 1, e))?;
+        }
+
+        Ok(Self::build(positions, None))
+    }
+
+    pub fn from_matrix(origin: Position, spacing: i32, matrix: Matrix) -> Result<Self> {
+        Self::validate_len(matrix.len())?;
+        Self::validate_position(origin)?;
+        Self::validate_spacing(spacing)?;
+
+        let layout = Self::new(generators::matrix(origin, spacing, &matrix))?;
+        Ok(Self { matrix: Some(matrix), ..layout })
+    }
+
+    pub fn validate_position(position: Position) -> Result<()> {
+        let range = -Self::MAX_COORDINATE..=Self::MAX_COORDINATE;
+
+        if !range.contains(&position.x) || !range.contains(&position.y) {
+            anyhow::bail!("Coordinates ({}, {}) must be within ±{}", position.x, position.y, Self::MAX_COORDINATE);
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_spacing(spacing: i32) -> Result<()> {
+        if !(1..=Self::MAX_SPACING).contains(&spacing) {
+            anyhow::bail!("Spacing must be in the range 1-{}", Self::MAX_SPACING);
+        }
+
+        Ok(())
+    }
+
+    // see import for the supported formats
+    pub fn from_json(input: &str) -> Result<Self> {
+        import::parse_json(input)
+    }
+
+    pub fn from_csv(input: &str) -> Result<Self> {
+        import::parse_csv(input)
+    }
+
+    // lights in a line, one unit apart
+    pub fn straight(count: usize) -> Result<Self> {
+        Self::validate_len(count)?;
+        Self::new(generators::straight(count, 1))
+    }
+
+    pub fn circle(count: usize) -> Result<Self> {
+        // large enough so that rounding does not merge lights
+        const RADIUS: i32 = 10000;
+
+        Self::validate_len(count)?;
+        Self::new(generators::circle(Position::new(0, 0), RADIUS, count))
+    }
+
+    // 10x10 grid, wired from the bottom left corner, one line left to right then the next one right to left
+    pub fn default_grid() -> Self {
+        let matrix = Matrix {
+            columns: 10,
+            rows: 10,
+            wiring: Wiring {
+                start: Corner::BottomLeft,
+                direction: Direction::Rows,
+                serpentine: true,
+            },
+        };
+
+        Self::build(generators::matrix(Position::new(0, 0), 1, &matrix), Some(matrix))
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    pub fn matrix(&self) -> Option<&Matrix> {
+        self.matrix.as_ref()
+    }
+
+    // (top left, bottom right)
+    pub fn bounds(&self) -> (Position, Position) {
+        self.bounds
+    }
+
+    fn build(positions: Vec<Position>, matrix: Option<Matrix>) -> Self {
+        let mut min = positions[0];
+        let mut max = positions[0];
+
+        for position in positions.iter() {
+            min.x = min.x.min(position.x);
+            min.y = min.y.min(position.y);
+            max.x = max.x.max(position.x);
+            max.y = max.y.max(position.y);
+        }
+
+        Self {
+            positions,
+            matrix,
+            bounds: (min, max),
+        }
+    }
+
+    fn validate_len(len: usize) -> Result<()> {
+        if len == 0 {
+            anyhow::bail!("Layout must have at least one light");
+        }
+
+        if len > Self::MAX_LIGHTS {
+            anyhow::bail!("Layout has {} lights, maximum is {}", len, Self::MAX_LIGHTS);
+        }
+
+        Ok(())
+    }
+}
@@ -1,6 +1,13 @@
+pub mod compositor;
 pub mod frame;
 pub mod drawing;
+pub mod layout;
+pub mod output;
+pub mod power;
 pub mod scene;
+pub mod strip;
+pub mod transition;
 
-pub use scene::Scene;
+pub use layout::Layout;
+pub use scene::{Scene, Segment};
 pub use drawing::Color;
\ No newline at end of file
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::Color;
+
+// Hardware correction, the programs keep working with logical colors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Calibration {
+    // 255 keeps the colors
+    pub brightness: u8,
+    // red, green, blue, 1.0 keeps the colors
+    pub gamma: [f32; 3],
+    // rows give the output red, green and blue from the input ones
+    pub white_balance: [[f32; 3]; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            brightness: 255,
+            gamma: [1.0; 3],
+            white_balance: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+
+impl Calibration {
+    const MIN_GAMMA: f32 = 0.1;
+    const MAX_GAMMA: f32 = 5.0;
+
+    pub fn validate(&self) -> Result<()> {
+        for gamma in self.gamma {
+            if !(Self::MIN_GAMMA..=Self::MAX_GAMMA).contains(&gamma) {
+                anyhow::bail!("Gamma must be in the range {}-{}", Self::MIN_GAMMA, Self::MAX_GAMMA);
+            }
+        }
+
+        for coefficient in self.white_balance.iter().flatten() {
+            if !(0.0..=1.0).contains(coefficient) {
+                anyhow::bail!("White balance coefficients must be in the range 0-1");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Applies white balance, then gamma, then brightness so that it scales the power drawn linearly
+pub struct OutputStage {
+    calibration: Calibration,
+    // per channel, from balanced value to output value
+    tables: [[u8; 256]; 3],
+}
+
+impl OutputStage {
+    pub fn new(calibration: Calibration) -> Result<Self> {
+        calibration.validate()?;
+
+        let mut tables = [[0; 256]; 3];
+
+        for (table, gamma) in tables.iter_mut().zip(calibration.gamma) {
+            for (value, output) in table.iter_mut().enumerate() {
+                let corrected = (value as f32 / 255.0).powf(gamma) * 255.0;
+                *output = (corrected * calibration.brightness as f32 / 255.0).round() as u8;
+            }
+        }
+
+        Ok(Self { calibration, tables })
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    pub fn apply(&self, input: &[Color], output: &mut [Color]) {
+        for (output, color) in output.iter_mut().zip(input.iter()) {
+            *output = self.correct(color);
+        }
+    }
+
+    fn correct(&self, color: &Color) -> Color {
+        let input = [color.red() as f32, color.green() as f32, color.blue() as f32];
+
+        let channel = |index: usize| {
+            let row = self.calibration.white_balance[index];
+            let balanced = row[0] * input[0] + row[1] * input[1] + row[2] * input[2];
+            self.tables[index][balanced.round().clamp(0.0, 255.0) as usize]
+        };
+
+        Color::from_rgb(channel(0), channel(1), channel(2))
+    }
+}
+
+impl Default for OutputStage {
+    fn default() -> Self {
+        Self::new(Calibration::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correct(calibration: Calibration, color: u32) -> u32 {
+        let mut output = [Color::BLACK];
+        OutputStage::new(calibration).unwrap().apply(&[Color::from_packed(color)], &mut output);
+        output[0].packed()
+    }
+
+    #[test]
+    fn default_is_identity() {
+        let stage = OutputStage::default();
+        let input: Vec<Color> = (0..=255).map(|value| Color::from_rgb(value, 255 - value, value / 2)).collect();
+        let mut output = vec![Color::BLACK; input.len()];
+        stage.apply(&input, &mut output);
+
+        for (input, output) in input.iter().zip(output.iter()) {
+            assert_eq!(input.packed(), output.packed());
+        }
+    }
+
+    #[test]
+    fn corrections() {
+        assert_eq!(correct(Calibration { brightness: 128, ..Default::default() }, 0xFF8000), 0x804000);
+        assert_eq!(correct(Calibration { gamma: [2.0, 1.0, 1.0], ..Default::default() }, 0x808080), 0x408080);
+
+        let swap = [[0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]];
+        assert_eq!(correct(Calibration { white_balance: swap, ..Default::default() }, 0x102030), 0x302010);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(OutputStage::new(Calibration { gamma: [0.0, 1.0, 1.0], ..Default::default() }).is_err());
+        assert!(OutputStage::new(Calibration { gamma: [1.0, f32::NAN, 1.0], ..Default::default() }).is_err());
+        assert!(OutputStage::new(Calibration { white_balance: [[1.5, 0.0, 0.0]; 3], ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::Color;
+
+// Current drawn by the lights, defaults are typical for WS2812 at 5V
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PowerModel {
+    pub voltage: f32,
+    // mA of a channel at full value, red, green, blue
+    pub channel_current: [f32; 3],
+    // mA of a light that is off
+    pub idle_current: f32,
+    // mA for all the lights, the output is dimmed to stay under it
+    pub budget: Option<f32>,
+}
+
+impl Default for PowerModel {
+    fn default() -> Self {
+        Self {
+            voltage: 5.0,
+            channel_current: [20.0; 3],
+            idle_current: 1.0,
+            budget: None,
+        }
+    }
+}
+
+impl PowerModel {
+    pub fn validate(&self) -> Result<()> {
+        let values = [self.voltage, self.idle_current, self.budget.unwrap_or(0.0)];
+
+        if values.iter().chain(self.channel_current.iter()).any(|value| !value.is_finite() || *value < 0.0) {
+            anyhow::bail!("Power model values must be non-negative numbers");
+        }
+
+        Ok(())
+    }
+
+    // mA, without the idle current
+    fn color_current(&self, color: &Color) -> f32 {
+        let [red, green, blue] = self.channel_current;
+        (color.red() as f32 * red + color.green() as f32 * green + color.blue() as f32 * blue) / 255.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PowerReport {
+    // mA and W of the last frame, after limiting
+    pub current: f32,
+    pub power: f32,
+    // highest current and power since the last reset
+    pub peak_current: f32,
+    pub peak_power: f32,
+    // mA the last frame would draw without the limiter
+    pub requested_current: f32,
+    pub limited: bool,
+}
+
+// Estimates the current of each frame, and dims it if it goes over the budget
+pub struct PowerMeter {
+    model: PowerModel,
+    report: PowerReport,
+}
+
+impl PowerMeter {
+    pub fn new(model: PowerModel) -> Result<Self> {
+        model.validate()?;
+
+        Ok(Self {
+            model,
+            report: PowerReport::default(),
+        })
+    }
+
+    pub fn model(&self) -> &PowerModel {
+        &self.model
+    }
+
+    pub fn report(&self) -> &PowerReport {
+        &self.report
+    }
+
+    pub fn reset_peak(&mut self) {
+        self.report.peak_current = 0.0;
+        self.report.peak_power = 0.0;
+    }
+
+    pub fn apply(&mut self, lights: &mut [Color]) {
+        let idle = self.model.idle_current * lights.len() as f32;
+        let colors: f32 = lights.iter().map(|color| self.model.color_current(color)).sum();
+        let requested = idle + colors;
+
+        let mut current = requested;
+        let mut limited = false;
+
+        if let Some(budget) = self.model.budget {
+            // when all lights are off there is nothing to dim, even if the idle current is over the budget
+            if requested > budget && colors > 0.0 {
+                // rounded down so that the scaled colors stay under the budget
+                let factor = ((budget - idle).max(0.0) / colors).min(1.0);
+                let amount = (factor * 255.0).floor() as u8;
+
+                for light in lights.iter_mut() {
+                    *light = light.scale(amount);
+                }
+
+                current = idle + lights.iter().map(|color| self.model.color_current(color)).sum::<f32>();
+                limited = true;
+            }
+        }
+
+        let report = &mut self.report;
+        report.current = current;
+        report.power = current * self.model.voltage / 1000.0;
+        report.peak_current = report.peak_current.max(report.current);
+        report.peak_power = report.peak_power.max(report.power);
+        report.requested_current = requested;
+        report.limited = limited;
+    }
+}
+
+impl Default for PowerMeter {
+    fn default() -> Self {
+        Self::new(PowerModel::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter(budget: Option<f32>) -> PowerMeter {
+        PowerMeter::new(PowerModel { budget, ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn estimates_full_white() {
+        let mut meter = meter(None);
+        let mut lights = vec![Color::WHITE; 300];
+        meter.apply(&mut lights);
+
+        let report = meter.report();
+        assert_eq!(report.current, 18300.0);
+        assert_eq!(report.power, 91.5);
+        assert!(!report.limited);
+        assert!(lights.iter().all(|light| light.packed() == 0xFFFFFF));
+    }
+
+    #[test]
+    fn stays_under_budget() {
+        let colors = [Color::WHITE, Color::from_rgb(255, 0, 0), Color::from_rgb(10, 200, 30), Color::from_rgb(1, 1, 1)];
+
+        for budget in [50.0, 301.0, 1000.0, 5000.0, 10000.0] {
+            for color in colors {
+                let mut meter = meter(Some(budget));
+                let mut lights = vec![color; 300];
+                meter.apply(&mut lights);
+
+                let report = meter.report();
+                assert!(report.current <= budget.max(300.0), "{} mA over {} mA for {:?}", report.current, budget, color);
+                assert_eq!(report.limited, report.requested_current > budget);
+            }
+        }
+    }
+
+    #[test]
+    fn idle_over_budget() {
+        let mut meter = meter(Some(100.0));
+        let mut lights = vec![Color::BLACK; 300];
+        meter.apply(&mut lights);
+
+        // nothing can be dimmed
+        let report = meter.report();
+        assert_eq!(report.current, 300.0);
+        assert!(!report.limited);
+
+        // only the idle current is left
+        let mut lights = vec![Color::WHITE; 300];
+        meter.apply(&mut lights);
+        assert_eq!(meter.report().current, 300.0);
+        assert!(meter.report().limited);
+        assert!(lights.iter().all(|light| light.packed() == 0));
+    }
+
+    #[test]
+    fn peak() {
+        let mut meter = meter(None);
+        meter.apply(&mut [Color::WHITE; 10]);
+        meter.apply(&mut [Color::BLACK; 10]);
+        assert_eq!(meter.report().current, 10.0);
+        assert_eq!(meter.report().peak_current, 610.0);
+
+        meter.reset_peak();
+        meter.apply(&mut [Color::BLACK; 10]);
+        assert_eq!(meter.report().peak_current, 10.0);
+    }
+
+    #[test]
+    fn validate() {
+        assert!(PowerMeter::new(PowerModel { idle_current: 0.0, budget: Some(0.0), ..Default::default() }).is_ok());
+        assert!(PowerMeter::new(PowerModel { budget: Some(-1.0), ..Default::default() }).is_err());
+        assert!(PowerMeter::new(PowerModel { channel_current: [20.0, f32::NAN, 20.0], ..Default::default() }).is_err());
+    }
+}
@@ -1,48 +1,286 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
-use super::drawing::{clear, Circle, Color, Drawable, Fillable, Line, Point, SCREEN};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    compositor::Compositor,
+    output::{Calibration, OutputStage},
+    power::{PowerMeter, PowerModel, PowerReport},
+    drawing::{clear, Circle, Color, Drawable, Fillable, Line, Point, SCREEN},
+    layout::{Layout, Position},
+};
+
+// Part of the lights with its own index space, so that it can run its own program
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub name: String,
+    pub start: usize,
+    pub count: usize,
+}
+
+impl Segment {
+    pub const DEFAULT_NAME: &'static str = "main";
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.count
+    }
+}
 
 pub struct Scene {
     full: AtomicBool,
-    lights: [Color; Self::LIGHT_COUNT],
+    layout: Layout,
+    segments: Vec<Segment>,
+    compositor: Compositor,
+    // output of the compositor
+    lights: Vec<Color>,
+    output_stage: OutputStage,
+    // lights corrected for the hardware, for rendering and export
+    output: Vec<Color>,
+    power: PowerMeter,
+    // screen coordinates of each light, computed from the layout
+    centers: Vec<Point>,
+    light_radius: usize,
 }
 
 impl Scene {
-    pub const LIGHT_COUNT: usize = 100;
-
-    const LIGHT_RADIUS: usize = 10;
+    const MAX_LIGHT_RADIUS: usize = 10;
+    const MIN_LIGHT_RADIUS: usize = 2;
     const PADDING: usize = 100;
-    const HEIGHT_LIGHT_COUNT: usize = 10;
 
     // computed values
-    const PADDING_TO_CENTER: usize = Self::PADDING + Self::LIGHT_RADIUS;
-    const WIDTH_LIGHT_COUNT: usize = Self::LIGHT_COUNT / Self::HEIGHT_LIGHT_COUNT;
-    const SPACE_BETWEEN_LIGHTS_W: usize =
-        (SCREEN.size().width() - Self::PADDING_TO_CENTER * 2) / (Self::WIDTH_LIGHT_COUNT - 1);
-    const SPACE_BETWEEN_LIGHTS_H: usize =
-        (SCREEN.size().height() - Self::PADDING_TO_CENTER * 2) / (Self::HEIGHT_LIGHT_COUNT - 1);
+    const PADDING_TO_CENTER: usize = Self::PADDING + Self::MAX_LIGHT_RADIUS;
 
     pub fn new() -> Self {
+        Self::with_layout(Layout::default_grid())
+    }
+
+    pub fn with_layout(layout: Layout) -> Self {
+        let centers = Self::compute_centers(&layout);
+        let segments = vec![Segment {
+            name: Segment::DEFAULT_NAME.to_string(),
+            start: 0,
+            count: layout.len(),
+        }];
+
         Self {
             full: AtomicBool::new(true),
-            lights: [Color::BLACK; Self::LIGHT_COUNT],
+            compositor: Compositor::with_segments(&segments),
+            segments,
+            lights: vec![Color::BLACK; layout.len()],
+            output_stage: OutputStage::default(),
+            output: vec![Color::BLACK; layout.len()],
+            power: PowerMeter::default(),
+            light_radius: Self::compute_light_radius(&centers),
+            centers,
+            layout,
         }
     }
 
+    // lights are turned off, and segments are replaced by a single one
+    // layers of the first segment are kept on the new one, the others are removed
+    // if none is left, a default layer is added so that there is always one to run a program
+    // calibration and power model are kept, the power peak is reset as it was drawn by other lights
+    pub fn set_layout(&mut self, layout: Layout) {
+        let mut compositor = std::mem::replace(&mut self.compositor, Compositor::new());
+        let output_stage = std::mem::take(&mut self.output_stage);
+        let mut power = std::mem::take(&mut self.power);
+        *self = Self::with_layout(layout);
+        self.output_stage = output_stage;
+        power.reset_peak();
+        self.power = power;
+
+        compositor.retain(|layer| layer.segment == 0);
+        for layer in compositor.layers_mut() {
+            layer.resize(self.len());
+        }
+
+        if compositor.layers().is_empty() {
+            compositor.add(Segment::DEFAULT_NAME, 0, self.len()).unwrap();
+        }
+
+        self.compositor = compositor;
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    // relative to the top left corner of the layout
+    pub fn light_position(&self, index: usize) -> Position {
+        let (min, _) = self.layout.bounds();
+        let position = self.layout.positions()[index];
+        Position::new(position.x - min.x, position.y - min.y)
+    }
+
+    // (width, height), both ends included so that a 10x10 grid is 10 wide
+    pub fn size(&self) -> (i32, i32) {
+        let (min, max) = self.layout.bounds();
+        (max.x - min.x + 1, max.y - min.y + 1)
+    }
+
+    // only on matrix layouts
+    pub fn matrix_index(&self, column: usize, row: usize) -> Option<usize> {
+        let matrix = self.layout.matrix()?;
+
+        if column >= matrix.columns || row >= matrix.rows {
+            return None;
+        }
+
+        Some(matrix.index(column, row))
+    }
+
     pub fn reset(&mut self) {
         self.full.store(true, Ordering::Relaxed);
 
+        self.compositor.reset();
+
         for light in self.lights.iter_mut() {
             *light = Color::BLACK;
         }
+
+        self.output.fill(Color::BLACK);
     }
 
-    pub fn get_light_color(&self, index: usize) -> Color {
-        self.lights[index]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
     }
 
-    pub fn set_light_color(&mut self, index: usize, color: Color) {
-        self.lights[index] = color;
+    pub fn find_segment(&self, name: &str) -> Option<usize> {
+        self.segments.iter().position(|segment| segment.name == name)
+    }
+
+    // segments must not overlap, lights outside of any segment stay off
+    // errors refer to the segments by their position in the list, starting at 1
+    pub fn validate_segments(&self, segments: &[Segment]) -> Result<()> {
+        if segments.is_empty() {
+            anyhow::bail!("There must be at least one segment");
+        }
+
+        for (index, segment) in segments.iter().enumerate() {
+            if segment.name.is_empty() {
+                anyhow::bail!("Segment {}: name must not be empty", index + 1);
+            }
+
+            if segments[..index].iter().any(|other| other.name == segment.name) {
+                anyhow::bail!("Segment {}: duplicate name: {}", index + 1, segment.name);
+            }
+
+            if segment.count == 0 {
+                anyhow::bail!("Segment {}: must have at least one light", index + 1);
+            }
+
+            let end = segment.start.checked_add(segment.count);
+
+            if end.is_none_or(|end| end > self.len()) {
+                anyhow::bail!(
+                    "Segment {}: lights {}-{} are outside of the layout (0-{})",
+                    index + 1,
+                    segment.start,
+                    segment.start.saturating_add(segment.count).saturating_sub(1),
+                    self.len() - 1
+                );
+            }
+        }
+
+        let mut sorted: Vec<usize> = (0..segments.len()).collect();
+        sorted.sort_by_key(|index| segments[*index].start);
+
+        for pair in sorted.windows(2) {
+            if segments[pair[0]].range().end > segments[pair[1]].start {
+                anyhow::bail!("Segments {} and {} overlap", pair[0].min(pair[1]) + 1, pair[0].max(pair[1]) + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    // layers are replaced by one per segment
+    pub fn set_segments(&mut self, segments: Vec<Segment>) -> Result<()> {
+        self.validate_segments(&segments)?;
+
+        self.compositor.set_segments(&segments);
+        self.segments = segments;
+        self.reset();
+
+        Ok(())
+    }
+
+    pub fn compositor(&self) -> &Compositor {
+        &self.compositor
+    }
+
+    pub fn compositor_mut(&mut self) -> &mut Compositor {
+        &mut self.compositor
+    }
+
+    pub fn add_layer(&mut self, name: &str, segment: usize) -> Result<usize> {
+        let len = self.segments[segment].count;
+        self.compositor.add(name, segment, len)
+    }
+
+    pub fn layer_range(&self, layer: usize) -> Range<usize> {
+        self.segments[self.compositor.layer(layer).segment].range()
+    }
+
+    pub fn layer_lights(&self, layer: usize, buffer: usize) -> &[Color] {
+        self.compositor.layer(layer).buffer(buffer)
+    }
+
+    pub fn layer_lights_mut(&mut self, layer: usize, buffer: usize) -> &mut [Color] {
+        self.compositor.layer_mut(layer).buffer_mut(buffer)
+    }
+
+    // lights of the current program
+    pub fn reset_layer(&mut self, layer: usize) {
+        self.compositor.layer_mut(layer).reset_active();
+    }
+
+    pub fn compose(&mut self, now: Duration) {
+        self.compositor.compose(&self.segments, &mut self.lights, now);
+        self.output_stage.apply(&self.lights, &mut self.output);
+        self.power.apply(&mut self.output);
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        self.output_stage.calibration()
+    }
+
+    // applied from the next frame
+    pub fn set_calibration(&mut self, calibration: Calibration) -> Result<()> {
+        self.output_stage = OutputStage::new(calibration)?;
+        Ok(())
+    }
+
+    pub fn power_model(&self) -> &PowerModel {
+        self.power.model()
+    }
+
+    // the peak is reset
+    pub fn set_power_model(&mut self, model: PowerModel) -> Result<()> {
+        self.power = PowerMeter::new(model)?;
+        Ok(())
+    }
+
+    pub fn power_report(&self) -> &PowerReport {
+        self.power.report()
+    }
+
+    pub fn reset_power_peak(&mut self) {
+        self.power.reset_peak();
+    }
+
+    // corrected lights of the last frame, what the hardware shows
+    pub fn output(&self) -> &[Color] {
+        &self.output
     }
 
     pub fn render(&self) {
@@ -55,64 +293,156 @@ impl Scene {
     }
 
     fn render_background(&self) {
-        let mut points: [Point; Self::LIGHT_COUNT] = [Point::new(0, 0); Self::LIGHT_COUNT];
+        clear(Color::BLACK);
 
-        for x in 0..Self::WIDTH_LIGHT_COUNT {
-            for y in 0..Self::HEIGHT_LIGHT_COUNT {
-                let center = Point::new(
-                    (Self::PADDING_TO_CENTER + x * Self::SPACE_BETWEEN_LIGHTS_W) as isize,
-                    (Self::PADDING_TO_CENTER + y * Self::SPACE_BETWEEN_LIGHTS_H) as isize,
-                );
+        // wiring
+        for pair in self.centers.windows(2) {
+            let line = Line::new(pair[0], pair[1]);
+            line.draw(Color::WHITE);
+        }
 
-                let index = self.coord_to_index(x, y);
+        for center in self.centers.iter() {
+            Circle::new(*center, self.light_radius + 1).fill(Color::WHITE);
+            Circle::new(*center, self.light_radius).fill(Color::BLACK);
+        }
+    }
 
-                points[index] = center;
-            }
+    fn render_lights(&self) {
+        for (center, color) in self.centers.iter().zip(self.output.iter()) {
+            Circle::new(*center, self.light_radius).fill(*color);
         }
+    }
 
-        clear(Color::BLACK);
+    // scale the layout to fit the screen, keeping its aspect ratio
+    fn compute_centers(layout: &Layout) -> Vec<Point> {
+        let (min, max) = layout.bounds();
+        let width = (max.x - min.x) as f64;
+        let height = (max.y - min.y) as f64;
 
-        for i in 0..(Self::LIGHT_COUNT - 1) {
-            let line = Line::new(points[i], points[i + 1]);
-            line.draw(Color::WHITE);
-        }
+        let available_width = (SCREEN.size().width() - Self::PADDING_TO_CENTER * 2) as f64;
+        let available_height = (SCREEN.size().height() - Self::PADDING_TO_CENTER * 2) as f64;
 
-        for x in 0..Self::WIDTH_LIGHT_COUNT {
-            for y in 0..Self::HEIGHT_LIGHT_COUNT {
-                let center = Point::new(
-                    (Self::PADDING_TO_CENTER + x * Self::SPACE_BETWEEN_LIGHTS_W) as isize,
-                    (Self::PADDING_TO_CENTER + y * Self::SPACE_BETWEEN_LIGHTS_H) as isize,
-                );
+        let scale_x = if width > 0.0 { available_width / width } else { f64::INFINITY };
+        let scale_y = if height > 0.0 { available_height / height } else { f64::INFINITY };
+        let scale = scale_x.min(scale_y);
+        let scale = if scale.is_finite() { scale } else { 0.0 };
 
-                Circle::new(center, Self::LIGHT_RADIUS + 1).fill(Color::WHITE);
-                Circle::new(center, Self::LIGHT_RADIUS).fill(Color::BLACK);
-            }
-        }
+        // center the layout in the available space
+        let offset_x = Self::PADDING_TO_CENTER as f64 + (available_width - width * scale) / 2.0;
+        let offset_y = Self::PADDING_TO_CENTER as f64 + (available_height - height * scale) / 2.0;
+
+        layout
+            .positions()
+            .iter()
+            .map(|position| {
+                Point::new(
+                    (offset_x + (position.x - min.x) as f64 * scale).round() as isize,
+                    (offset_y + (position.y - min.y) as f64 * scale).round() as isize,
+                )
+            })
+            .collect()
     }
 
-    fn render_lights(&self) {
-        for x in 0..Self::WIDTH_LIGHT_COUNT {
-            for y in 0..Self::HEIGHT_LIGHT_COUNT {
-                let center = Point::new(
-                    (Self::PADDING_TO_CENTER + x * Self::SPACE_BETWEEN_LIGHTS_W) as isize,
-                    (Self::PADDING_TO_CENTER + y * Self::SPACE_BETWEEN_LIGHTS_H) as isize,
-                );
+    // lights must not overlap their neighbors on the wire
+    fn compute_light_radius(centers: &[Point]) -> usize {
+        let min_distance = centers
+            .windows(2)
+            .map(|pair| {
+                let dx = (pair[1].x() - pair[0].x()) as f64;
+                let dy = (pair[1].y() - pair[0].y()) as f64;
+                (dx * dx + dy * dy).sqrt()
+            })
+            .filter(|distance| *distance > 0.0)
+            .fold(f64::INFINITY, f64::min);
 
-                let index = self.coord_to_index(x, y);
-                Circle::new(center, Self::LIGHT_RADIUS).fill(self.lights[index]);
-            }
+        if !min_distance.is_finite() {
+            return Self::MAX_LIGHT_RADIUS;
         }
+
+        ((min_distance / 2.0) as usize)
+            .saturating_sub(1)
+            .clamp(Self::MIN_LIGHT_RADIUS, Self::MAX_LIGHT_RADIUS)
     }
+}
 
-    fn coord_to_index(&self, x: usize, y: usize) -> usize {
-        // bottom to top
-        // odd lines : left to right
-        // even lines : right to left
-        let y_index = Self::HEIGHT_LIGHT_COUNT - y - 1;
-        if y_index % 2 == 0 {
-            y_index * Self::WIDTH_LIGHT_COUNT + x
-        } else {
-            y_index * Self::WIDTH_LIGHT_COUNT + Self::WIDTH_LIGHT_COUNT - x - 1
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(name: &str, start: usize, count: usize) -> Segment {
+        Segment { name: name.to_string(), start, count }
+    }
+
+    fn set_segments(segments: Vec<Segment>) -> Result<()> {
+        Scene::new().set_segments(segments)
+    }
+
+    fn error(segments: Vec<Segment>) -> String {
+        set_segments(segments).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn segments() {
+        let mut scene = Scene::new();
+        scene.set_segments(vec![segment("b", 50, 50), segment("a", 0, 50)]).unwrap();
+
+        assert_eq!(scene.find_segment("a"), Some(1));
+        assert_eq!(scene.compositor().layers().len(), 2);
+        assert_eq!(scene.layer_lights(scene.compositor().find("b").unwrap(), 0).len(), 50);
+
+        // gaps are allowed
+        assert!(set_segments(vec![segment("a", 10, 10), segment("b", 90, 10)]).is_ok());
+    }
+
+    #[test]
+    fn segment_errors() {
+        assert_eq!(error(vec![]), "There must be at least one segment");
+        assert_eq!(error(vec![segment("a", 0, 10), segment("", 10, 10)]), "Segment 2: name must not be empty");
+        assert_eq!(error(vec![segment("a", 0, 10), segment("a", 10, 10)]), "Segment 2: duplicate name: a");
+        assert_eq!(error(vec![segment("a", 0, 0)]), "Segment 1: must have at least one light");
+        assert_eq!(error(vec![segment("a", 95, 10)]), "Segment 1: lights 95-104 are outside of the layout (0-99)");
+        assert_eq!(
+            error(vec![segment("a", 0, 10), segment("b", usize::MAX, 2)]),
+            format!("Segment 2: lights {}-{} are outside of the layout (0-99)", usize::MAX, usize::MAX - 1)
+        );
+        assert_eq!(error(vec![segment("a", 50, 10), segment("b", 0, 51)]), "Segments 1 and 2 overlap");
+    }
+
+    #[test]
+    fn rejected_segments_are_not_applied() {
+        let mut scene = Scene::new();
+        assert!(scene.set_segments(vec![segment("a", 0, 10), segment("a", 10, 10)]).is_err());
+
+        assert_eq!(scene.segments().len(), 1);
+        assert_eq!(scene.segments()[0].name, Segment::DEFAULT_NAME);
+    }
+
+    #[test]
+    fn set_layout_keeps_a_layer() {
+        let mut scene = Scene::new();
+        scene.set_segments(vec![segment("a", 0, 50), segment("b", 50, 50)]).unwrap();
+
+        let removed = scene.compositor().find("a").unwrap();
+        scene.compositor_mut().remove(removed);
+        scene.set_layout(Layout::default_grid());
+
+        let layers = scene.compositor().layers();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name, Segment::DEFAULT_NAME);
+        assert_eq!(layers[0].segment, 0);
+        assert_ne!(layers[0].id(), removed);
+        assert_eq!(scene.layer_lights(layers[0].id(), 0).len(), 100);
+    }
+
+    #[test]
+    fn set_layout_keeps_power_model() {
+        let mut scene = Scene::new();
+        let model = PowerModel { budget: Some(1000.0), ..Default::default() };
+        scene.set_power_model(model.clone()).unwrap();
+        scene.compose(Duration::ZERO);
+
+        scene.set_layout(Layout::default_grid());
+        assert_eq!(scene.power_model().budget, model.budget);
+        assert_eq!(scene.power_report().peak_current, 0.0);
     }
 }
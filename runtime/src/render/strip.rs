@@ -0,0 +1,54 @@
+use super::Color;
+
+// Operations on a run of lights, ranges include both ends
+
+// amount 0 keeps the lights, 255 turns them off
+pub fn fade_all(lights: &mut [Color], amount: u8) {
+    for light in lights.iter_mut() {
+        *light = light.scale(255 - amount);
+    }
+}
+
+pub fn fill(lights: &mut [Color], start: usize, end: usize, color: Color) {
+    for light in lights[start..=end].iter_mut() {
+        *light = color;
+    }
+}
+
+pub fn gradient(lights: &mut [Color], start: usize, end: usize, color1: Color, color2: Color) {
+    let span = (end - start).max(1);
+
+    for (offset, light) in lights[start..=end].iter_mut().enumerate() {
+        *light = color1.blend(&color2, (offset * 255 / span) as u8);
+    }
+}
+
+// move the lights towards the end of the strip (or the beginning if negative), new lights are black
+pub fn shift(lights: &mut [Color], offset: isize) {
+    let len = lights.len();
+    let count = offset.unsigned_abs().min(len);
+
+    if offset >= 0 {
+        lights.copy_within(..len - count, count);
+        lights[..count].fill(Color::BLACK);
+    } else {
+        lights.copy_within(count.., 0);
+        lights[len - count..].fill(Color::BLACK);
+    }
+}
+
+// same as shift, but the lights that go out on one end come back on the other end
+pub fn rotate(lights: &mut [Color], offset: isize) {
+    let count = offset.rem_euclid(lights.len() as isize) as usize;
+    lights.rotate_right(count);
+}
+
+// copy the first half of the range onto the second half, reversed
+pub fn mirror(lights: &mut [Color], start: usize, end: usize) {
+    let range = &mut lights[start..=end];
+    let len = range.len();
+
+    for index in 0..len / 2 {
+        range[len - 1 - index] = range[index];
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::Color;
+
+// How a layer goes from its previous program to a new one, durations in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Transition {
+    // the new program starts on black lights
+    #[default]
+    Cut,
+    Crossfade {
+        duration: u32,
+    },
+    // the new program takes over from the first light to the last one (or the opposite if reversed)
+    Wipe {
+        duration: u32,
+        #[serde(default)]
+        reverse: bool,
+    },
+}
+
+impl Transition {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Transition::Cut => Duration::ZERO,
+            Transition::Crossfade { duration } | Transition::Wipe { duration, .. } => {
+                Duration::from_millis(*duration as u64)
+            }
+        }
+    }
+
+    // light at index among len, progress 0 shows only the old lights, 255 only the new ones
+    pub fn mix(&self, from: &Color, to: &Color, index: usize, len: usize, progress: u8) -> Color {
+        match self {
+            Transition::Cut => *to,
+            Transition::Crossfade { .. } => from.blend(to, progress),
+            Transition::Wipe { reverse, .. } => {
+                let edge = len * progress as usize / 255;
+                let position = if *reverse { len - 1 - index } else { index };
+
+                if position < edge { *to } else { *from }
+            }
+        }
+    }
+}
+
+// Transition in progress on a layer, from one of its buffers to the other
+pub struct RunningTransition {
+    transition: Transition,
+    from: usize,
+    start: Duration,
+}
+
+impl RunningTransition {
+    pub fn new(transition: Transition, from: usize, start: Duration) -> Self {
+        Self { transition, from, start }
+    }
+
+    pub fn finished(&self, now: Duration) -> bool {
+        now.saturating_sub(self.start) >= self.transition.duration()
+    }
+
+    pub fn progress(&self, now: Duration) -> u8 {
+        let duration = self.transition.duration().as_micros().max(1);
+        let elapsed = now.saturating_sub(self.start).as_micros().min(duration);
+
+        (elapsed * 255 / duration) as u8
+    }
+
+    // mixed per light, so that no buffer is allocated on each frame
+    pub fn mix(&self, buffers: &[Vec<Color>; 2], index: usize, progress: u8) -> Color {
+        let from = &buffers[self.from];
+        let to = &buffers[1 - self.from];
+
+        self.transition.mix(&from[index], &to[index], index, to.len(), progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FROM: Color = Color::from_rgb(255, 0, 0);
+    const TO: Color = Color::from_rgb(0, 0, 255);
+
+    fn mix(transition: Transition, progress: u8) -> Vec<u32> {
+        (0..10).map(|index| transition.mix(&FROM, &TO, index, 10, progress).packed()).collect()
+    }
+
+    #[test]
+    fn wipe() {
+        let wipe = Transition::Wipe { duration: 1000, reverse: false };
+        assert_eq!(mix(wipe, 0), [0xFF0000; 10]);
+        assert_eq!(mix(wipe, 255), [0x0000FF; 10]);
+        assert_eq!(mix(wipe, 128), [0x0000FF, 0x0000FF, 0x0000FF, 0x0000FF, 0x0000FF, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000]);
+    }
+
+    #[test]
+    fn wipe_reverse() {
+        let wipe = Transition::Wipe { duration: 1000, reverse: true };
+        assert_eq!(mix(wipe, 0), [0xFF0000; 10]);
+        assert_eq!(mix(wipe, 255), [0x0000FF; 10]);
+        assert_eq!(mix(wipe, 26), [0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0xFF0000, 0x0000FF]);
+    }
+
+    #[test]
+    fn crossfade() {
+        let crossfade = Transition::Crossfade { duration: 1000 };
+        assert_eq!(mix(crossfade, 0), [0xFF0000; 10]);
+        assert_eq!(mix(crossfade, 128), [0x7F0080; 10]);
+        assert_eq!(mix(crossfade, 255), [0x0000FF; 10]);
+    }
+
+    #[test]
+    fn running() {
+        let transition = RunningTransition::new(Transition::Crossfade { duration: 1000 }, 0, Duration::from_secs(1));
+        let buffers = [vec![FROM; 2], vec![TO; 2]];
+
+        let mix = |now| transition.mix(&buffers, 0, transition.progress(now)).packed();
+
+        assert_eq!(mix(Duration::from_secs(1)), 0xFF0000);
+        assert_eq!(mix(Duration::from_millis(1500)), 0x80007F);
+        assert!(!transition.finished(Duration::from_millis(1999)));
+        assert!(transition.finished(Duration::from_secs(2)));
+        assert_eq!(mix(Duration::from_secs(3)), 0x0000FF);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use wasm_timer::Instant;
+
+pub trait Clock: Sync + Send {
+    // monotonic time, from an arbitrary origin
+    fn now(&self) -> Duration;
+}
+
+pub struct RealTimeClock {
+    origin: Instant,
+}
+
+impl RealTimeClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Clock for RealTimeClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+// Clock that only moves when told to, for tests and offline rendering.
+// Clones share the same time, so one can be given to the VM and the other kept to drive it.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+}
+
+// Clock driven by another one, that can be paused, sped up, slowed down or moved forward manually.
+// Clones share the same state, so one can be given to the VM and the other kept to control it.
+#[derive(Clone)]
+pub struct SimulationClock {
+    state: Arc<Mutex<SimulationState>>,
+}
+
+struct SimulationState {
+    source: Box<dyn Clock>,
+    // source time and simulation time at the last change of speed/pause
+    source_base: Duration,
+    base: Duration,
+    speed: f64,
+    paused: bool,
+}
+
+impl SimulationState {
+    fn now(&self) -> Duration {
+        if self.paused {
+            return self.base;
+        }
+
+        let source_elapsed = self.source.now().saturating_sub(self.source_base);
+        self.base + source_elapsed.mul_f64(self.speed)
+    }
+
+    fn rebase(&mut self) {
+        self.base = self.now();
+        self.source_base = self.source.now();
+    }
+}
+
+impl SimulationClock {
+    pub fn new(source: Box<dyn Clock>) -> Self {
+        let source_base = source.now();
+
+        Self {
+            state: Arc::new(Mutex::new(SimulationState {
+                source,
+                source_base,
+                base: Duration::ZERO,
+                speed: 1.0,
+                paused: false,
+            })),
+        }
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rebase();
+        state.paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rebase();
+        state.paused = false;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.rebase();
+        state.speed = speed;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.rebase();
+        state.base += duration;
+    }
+}
+
+impl Clock for SimulationClock {
+    fn now(&self) -> Duration {
+        self.state.lock().unwrap().now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{
+        compiler,
+        vm::{executable::Executable, ExternalApi, VM},
+    };
+
+    // a single light, its red channel counts the wakeups of the program
+    #[derive(Clone, Default)]
+    struct CounterApi {
+        red: Arc<Mutex<u8>>,
+    }
+
+    impl ExternalApi for CounterApi {
+        fn len(&self) -> usize {
+            1
+        }
+
+        fn position(&self, _index: usize) -> (i32, i32) {
+            (0, 0)
+        }
+
+        fn width(&self) -> i32 {
+            1
+        }
+
+        fn height(&self) -> i32 {
+            1
+        }
+
+        fn matrix_index(&self, _x: usize, _y: usize) -> Option<usize> {
+            None
+        }
+
+        fn get(&self, _index: usize) -> (u8, u8, u8) {
+            (*self.red.lock().unwrap(), 0, 0)
+        }
+
+        fn set(&self, _index: usize, color: (u8, u8, u8)) {
+            *self.red.lock().unwrap() = color.0;
+        }
+
+        fn fade_all(&self, _amount: u8) {}
+
+        fn fill(&self, _start: usize, _end: usize, _color: (u8, u8, u8)) {}
+
+        fn gradient(&self, _start: usize, _end: usize, _color1: (u8, u8, u8), _color2: (u8, u8, u8)) {}
+
+        fn shift(&self, _offset: isize) {}
+
+        fn rotate(&self, _offset: isize) {}
+
+        fn mirror(&self, _start: usize, _end: usize) {}
+
+        fn input(&self, _channel: u32) -> i32 {
+            0
+        }
+    }
+
+    // loop { count = count + 1; set(0, count, 0, 0); sleep(100) }
+    const PROGRAM: &str = r#"{
+        "variables": ["count"],
+        "body": { "type": "loop", "body": { "type": "sequence", "items": [
+            { "type": "set-variable", "variable": "count", "value": { "type": "arithmetic", "op": "add",
+                "op1": { "type": "get-variable", "variable": "count" }, "op2": { "type": "literal", "value": 1 } } },
+            { "type": "set", "index": { "type": "literal", "value": 0 },
+                "red": { "type": "get-variable", "variable": "count" },
+                "green": { "type": "literal", "value": 0 }, "blue": { "type": "literal", "value": 0 } },
+            { "type": "sleep", "delay": { "type": "literal", "value": 100 } }
+        ] } }
+    }"#;
+
+    // wakeup count after each frame, the clock moves by frame_duration between frames
+    fn run(frame_duration: Duration, frames: usize) -> Vec<u8> {
+        let exec = Executable::from_text(&compiler::compile(PROGRAM).unwrap()).unwrap();
+        let api = CounterApi::default();
+        let clock = ManualClock::new();
+
+        let mut vm = VM::new(Box::new(api.clone()), Box::new(clock.clone()));
+        vm.load_executable(exec, 0);
+
+        let mut counts = Vec::new();
+        for _ in 0..frames {
+            vm.tick();
+            counts.push(*api.red.lock().unwrap());
+            clock.advance(frame_duration);
+        }
+
+        counts
+    }
+
+    #[test]
+    fn sleep_does_not_drift() {
+        // 60 FPS: the frames that notice the wakeups are late by up to 16ms, which must not add up
+        // wakeups at 0, 100, ..., 900 are all seen by the frame at 992ms
+        let counts = run(Duration::from_millis(16), 63);
+        assert_eq!(counts[62], 10);
+    }
+
+    #[test]
+    fn sleep_catches_up_small_lags() {
+        // at 300ms, the deadlines 100, 200 and 300 are all run in the same frame
+        let counts = run(Duration::from_millis(300), 2);
+        assert_eq!(counts, vec![1, 4]);
+    }
+
+    #[test]
+    fn sleep_skips_long_lags() {
+        // more than MAX_LAG behind (eg: background tab): run once and start again from now
+        let counts = run(Duration::from_millis(2000), 3);
+        assert_eq!(counts, vec![1, 2, 3]);
+    }
+}
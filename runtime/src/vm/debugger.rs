@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use super::{executable::DebugInfo, machine::Machine};
+
+#[derive(Default)]
+pub struct Breakpoints {
+    addresses: BTreeSet<usize>,
+    blocks: BTreeSet<String>,
+    // addresses of the loaded program to break on, computed from both sets above
+    resolved: BTreeSet<usize>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_address(&mut self, address: usize) {
+        self.addresses.insert(address);
+    }
+
+    pub fn remove_address(&mut self, address: usize) {
+        self.addresses.remove(&address);
+    }
+
+    pub fn add_block(&mut self, id: &str) {
+        self.blocks.insert(id.to_string());
+    }
+
+    pub fn remove_block(&mut self, id: &str) {
+        self.blocks.remove(id);
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+        self.blocks.clear();
+        self.resolved.clear();
+    }
+
+    // must be called when breakpoints or the loaded program change
+    pub fn resolve(&mut self, debug_info: Option<&DebugInfo>) {
+        self.resolved = self.addresses.clone();
+
+        if let Some(debug_info) = debug_info {
+            for id in self.blocks.iter() {
+                self.resolved.extend(debug_info.statement_addresses(id));
+            }
+        }
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.resolved.contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugState {
+    pub paused: bool,
+    pub task: usize,
+    pub pc: usize,
+    pub block: Option<String>,
+    pub stack: Vec<i32>,
+    pub locals: Vec<LocalInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalInfo {
+    pub name: String,
+    pub value: i32,
+}
+
+impl DebugState {
+    pub fn capture(machine: &Machine, debug_info: &DebugInfo, paused: bool) -> Self {
+        let pc = machine.instruction_index();
+
+        let locals = machine
+            .locals()
+            .iter()
+            .enumerate()
+            .map(|(index, value)| LocalInfo {
+                name: debug_info
+                    .variables
+                    .get(index)
+                    .cloned()
+                    .unwrap_or_else(|| format!("${}", index)),
+                value: *value,
+            })
+            .collect();
+
+        Self {
+            paused,
+            task: machine.current_task(),
+            pc,
+            block: debug_info.statement_at(pc).map(str::to_string),
+            stack: machine.stack().to_vec(),
+            locals,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::Result;
+
+use super::{
+    executable::{EntryPoint, Event},
+    machine::Machine,
+};
+
+pub struct EventDispatcher {
+    entry_points: Vec<EntryPoint>,
+    // (entry point index, next deadline relative to the program start)
+    timers: Vec<(usize, Duration)>,
+    // entry points waiting to be run, in order
+    pending: VecDeque<usize>,
+}
+
+impl EventDispatcher {
+    pub fn new(entry_points: &[EntryPoint]) -> Self {
+        let mut dispatcher = Self {
+            entry_points: entry_points.to_vec(),
+            timers: Vec::new(),
+            pending: VecDeque::new(),
+        };
+
+        for (index, entry_point) in entry_points.iter().enumerate() {
+            match entry_point.event {
+                Event::Start => dispatcher.enqueue(index),
+                Event::Timer { period } => {
+                    dispatcher.timers.push((index, Duration::from_millis(period as u64)));
+                }
+                Event::Input { .. } => {}
+            }
+        }
+
+        dispatcher
+    }
+
+    pub fn input_changed(&mut self, channel: u32) {
+        for index in 0..self.entry_points.len() {
+            if self.entry_points[index].event == (Event::Input { channel }) {
+                self.enqueue(index);
+            }
+        }
+    }
+
+    pub fn dispatch(&mut self, machine: &mut Machine) -> Result<()> {
+        self.poll_timers(machine.clock().now());
+
+        // a handler only has one running instance: if it is still running, its event stays queued until it exits
+        let mut waiting = VecDeque::new();
+
+        while let Some(index) = self.pending.pop_front() {
+            if machine.handler_running(index) {
+                waiting.push_back(index);
+                continue;
+            }
+
+            machine.spawn_handler(index, self.entry_points[index].address as usize)?;
+        }
+
+        self.pending = waiting;
+
+        Ok(())
+    }
+
+    // no event can occur anymore
+    pub fn idle(&self) -> bool {
+        self.pending.is_empty()
+            && self
+                .entry_points
+                .iter()
+                .all(|entry_point| entry_point.event == Event::Start)
+    }
+
+    fn poll_timers(&mut self, now: Duration) {
+        let mut fired = Vec::new();
+
+        for (index, deadline) in self.timers.iter_mut() {
+            let Event::Timer { period } = self.entry_points[*index].event else {
+                continue;
+            };
+
+            if *deadline > now {
+                continue;
+            }
+
+            // if we are late by several periods, only fire once
+            while *deadline <= now {
+                *deadline += Duration::from_millis(period as u64);
+            }
+
+            fired.push(*index);
+        }
+
+        for index in fired {
+            self.enqueue(index);
+        }
+    }
+
+    fn enqueue(&mut self, index: usize) {
+        // coalesce events of a handler that is already waiting
+        if !self.pending.contains(&index) {
+            self.pending.push_back(index);
+        }
+    }
+}
@@ -1,22 +1,211 @@
-use std::{fmt, io::Cursor};
+use std::{fmt, io::{Cursor, Read, Write}};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::Serialize;
 use super::i24::i24;
 use anyhow::Result;
 
-const MAGIC: u32 = 0x00BABE00;
+// the low byte is the format version, bumped on every change of the layout or of the opcodes
+// so that executables saved by an older runtime are rejected instead of misread
+const MAGIC: u32 = 0x00BABE01;
 
 pub struct Executable {
     stack_size: u32,
     locals_size: u32,
+    parameters: Vec<ParameterDefinition>,
+    entry_points: Vec<EntryPoint>,
+    debug_info: DebugInfo,
     code: Vec<OpCode>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterDefinition {
+    pub name: String,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl ParameterDefinition {
+    fn from_raw(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        let name = read_string(reader)?;
+        let default = reader.read_i32::<LittleEndian>()?;
+        let min = reader.read_i32::<LittleEndian>()?;
+        let max = reader.read_i32::<LittleEndian>()?;
+
+        Ok(Self { name, default, min, max })
+    }
+
+    fn to_raw(&self, writer: &mut Cursor<Vec<u8>>) {
+        write_string(writer, &self.name);
+        writer.write_i32::<LittleEndian>(self.default).unwrap();
+        writer.write_i32::<LittleEndian>(self.min).unwrap();
+        writer.write_i32::<LittleEndian>(self.max).unwrap();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Start,
+    Timer { period: u32 },
+    Input { channel: u32 },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Start => write!(f, "Start"),
+            Event::Timer { period } => write!(f, "Timer({})", period),
+            Event::Input { channel } => write!(f, "Input({})", channel),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EntryPoint {
+    pub event: Event,
+    pub address: u32,
+}
+
+impl EntryPoint {
+    const KIND_START: u8 = 0;
+    const KIND_TIMER: u8 = 1;
+    const KIND_INPUT: u8 = 2;
+
+    fn from_raw(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        let kind = reader.read_u8()?;
+        let arg = reader.read_u32::<LittleEndian>()?;
+        let address = reader.read_u32::<LittleEndian>()?;
+
+        let event = match kind {
+            Self::KIND_START => Event::Start,
+            // a zero period would never let the timer catch up with the clock
+            Self::KIND_TIMER if arg == 0 => anyhow::bail!("Invalid timer period: 0"),
+            Self::KIND_TIMER => Event::Timer { period: arg },
+            Self::KIND_INPUT => Event::Input { channel: arg },
+            _ => anyhow::bail!("Invalid entry point kind: {}", kind),
+        };
+
+        Ok(Self { event, address })
+    }
+
+    fn to_raw(self, writer: &mut Cursor<Vec<u8>>) {
+        let (kind, arg) = match self.event {
+            Event::Start => (Self::KIND_START, 0),
+            Event::Timer { period } => (Self::KIND_TIMER, period),
+            Event::Input { channel } => (Self::KIND_INPUT, channel),
+        };
+
+        writer.write_u8(kind).unwrap();
+        writer.write_u32::<LittleEndian>(arg).unwrap();
+        writer.write_u32::<LittleEndian>(self.address).unwrap();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    // local names, by index
+    pub variables: Vec<String>,
+    // first instruction of each statement (block of the designer), ordered by address
+    pub statements: Vec<StatementInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatementInfo {
+    pub address: u32,
+    pub id: String,
+}
+
+impl DebugInfo {
+    fn from_raw(reader: &mut Cursor<&[u8]>) -> Result<Self> {
+        let variables_count = read_count(reader, 4)?;
+        let mut variables = Vec::new();
+        for _ in 0..variables_count {
+            variables.push(read_string(reader)?);
+        }
+
+        let statements_count = read_count(reader, 8)?;
+        let mut statements = Vec::new();
+        for _ in 0..statements_count {
+            let address = reader.read_u32::<LittleEndian>()?;
+            let id = read_string(reader)?;
+            statements.push(StatementInfo { address, id });
+        }
+
+        Ok(Self { variables, statements })
+    }
+
+    fn to_raw(&self, writer: &mut Cursor<Vec<u8>>) {
+        writer.write_u32::<LittleEndian>(self.variables.len() as u32).unwrap();
+        for variable in &self.variables {
+            write_string(writer, variable);
+        }
+
+        writer.write_u32::<LittleEndian>(self.statements.len() as u32).unwrap();
+        for statement in &self.statements {
+            writer.write_u32::<LittleEndian>(statement.address).unwrap();
+            write_string(writer, &statement.id);
+        }
+    }
+
+    pub fn is_statement_start(&self, address: usize) -> bool {
+        self.statements
+            .binary_search_by_key(&(address as u32), |statement| statement.address)
+            .is_ok()
+    }
+
+    // innermost statement containing the address
+    pub fn statement_at(&self, address: usize) -> Option<&str> {
+        let index = self
+            .statements
+            .partition_point(|statement| statement.address as usize <= address);
+
+        if index == 0 {
+            return None;
+        }
+
+        Some(&self.statements[index - 1].id)
+    }
+
+    pub fn statement_addresses<'a>(&'a self, id: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.statements
+            .iter()
+            .filter(move |statement| statement.id == id)
+            .map(|statement| statement.address as usize)
+    }
+}
+
+// count of items, or length of a string, each item taking at least min_size bytes
+// checked against what is left so that a corrupted executable cannot trigger a huge allocation or loop
+fn read_count(reader: &mut Cursor<&[u8]>, min_size: usize) -> Result<usize> {
+    let count = reader.read_u32::<LittleEndian>()? as usize;
+    let remaining = reader.get_ref().len().saturating_sub(reader.position() as usize);
+
+    if count.saturating_mul(min_size) > remaining {
+        anyhow::bail!("Invalid length: {} ({} bytes left)", count, remaining);
+    }
+
+    Ok(count)
+}
+
+fn read_string(reader: &mut Cursor<&[u8]>) -> Result<String> {
+    let len = read_count(reader, 1)?;
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer)?;
+
+    Ok(String::from_utf8(buffer)?)
+}
+
+fn write_string(writer: &mut Cursor<Vec<u8>>, value: &str) {
+    writer.write_u32::<LittleEndian>(value.len() as u32).unwrap();
+    writer.write_all(value.as_bytes()).unwrap();
+}
+
 impl Executable {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
         let mut reader = Cursor::new(raw);
 
         if reader.read_u32::<LittleEndian>()? != MAGIC {
-            anyhow::bail!("Invalid magic number");
+            anyhow::bail!("Invalid magic number (unsupported or corrupted executable)");
         }
 
         if reader.read_u32::<LittleEndian>()? != Self::compute_crc(raw) {
@@ -26,6 +215,22 @@ impl Executable {
         let stack_size = reader.read_u32::<LittleEndian>()?;
         let locals_size = reader.read_u32::<LittleEndian>()?;
 
+        // name length and 3 values
+        let parameters_count = read_count(&mut reader, 16)?;
+        let mut parameters = Vec::new();
+        for _ in 0..parameters_count {
+            parameters.push(ParameterDefinition::from_raw(&mut reader)?);
+        }
+
+        // kind, argument and address
+        let entry_points_count = read_count(&mut reader, 9)?;
+        let mut entry_points = Vec::new();
+        for _ in 0..entry_points_count {
+            entry_points.push(EntryPoint::from_raw(&mut reader)?);
+        }
+
+        let debug_info = DebugInfo::from_raw(&mut reader)?;
+
         let mut code = Vec::new();
         while (reader.position() as usize) < reader.get_ref().len() {
             let op =  OpCode::from_raw(reader.read_u32::<LittleEndian>()?);
@@ -35,6 +240,9 @@ impl Executable {
         Ok(Self { 
             stack_size,
             locals_size,
+            parameters,
+            entry_points,
+            debug_info,
             code,
         })
     }
@@ -49,6 +257,18 @@ impl Executable {
         writer.write_u32::<LittleEndian>(self.stack_size).unwrap();
         writer.write_u32::<LittleEndian>(self.locals_size).unwrap();
 
+        writer.write_u32::<LittleEndian>(self.parameters.len() as u32).unwrap();
+        for parameter in &self.parameters {
+            parameter.to_raw(&mut writer);
+        }
+
+        writer.write_u32::<LittleEndian>(self.entry_points.len() as u32).unwrap();
+        for entry_point in &self.entry_points {
+            entry_point.to_raw(&mut writer);
+        }
+
+        self.debug_info.to_raw(&mut writer);
+
         for op in &self.code {
             writer.write_u32::<LittleEndian>(op.to_raw()).unwrap();
         }
@@ -83,10 +303,13 @@ impl Executable {
         general_purpose::STANDARD_NO_PAD.encode(self.to_raw())
     }
 
-    pub fn new(stack_size: u32, locals_size: u32, code: Vec<OpCode>) -> Self {
+    pub fn new(stack_size: u32, locals_size: u32, parameters: Vec<ParameterDefinition>, entry_points: Vec<EntryPoint>, debug_info: DebugInfo, code: Vec<OpCode>) -> Self {
         Self {
             stack_size,
             locals_size,
+            parameters,
+            entry_points,
+            debug_info,
             code,
         }
     }
@@ -99,6 +322,18 @@ impl Executable {
         self.locals_size as usize
     }
 
+    pub fn parameters(&self) -> &[ParameterDefinition] {
+        &self.parameters
+    }
+
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
     pub fn code(&self) -> &[OpCode] {
         &self.code
     }
@@ -109,6 +344,15 @@ impl fmt::Display for Executable {
         writeln!(f, "Executable")?;
         writeln!(f, "  StackSize={}", self.stack_size)?;
         writeln!(f, "  LocalsSize={}", self.locals_size)?;
+
+        for parameter in &self.parameters {
+            writeln!(f, "  Parameter({}, default={}, min={}, max={})", parameter.name, parameter.default, parameter.min, parameter.max)?;
+        }
+
+        for entry_point in &self.entry_points {
+            writeln!(f, "  EntryPoint({}, address={})", entry_point.event, entry_point.address)?;
+        }
+
         writeln!(f, "")?;
 
         for op in &self.code {
@@ -126,6 +370,7 @@ pub enum OpCode {
     PushConstant { value: i24 },
     PushVariable { index: u8 },
     PopVariable { index: u8 },
+    ParamGet { index: u8 },
     Pop,
 
     // Compare
@@ -150,15 +395,53 @@ pub enum OpCode {
     Div,
     Pow,
     Mod,
+
+    // Color
+    Rgb,
+    Hsv,
+    Hsl,
+    ColorRed,
+    ColorGreen,
+    ColorBlue,
+    Blend,
+    Scale,
+    AddSat,
+    PaletteLookup { palette: u8 },
+
+    // Noise
+    Noise1D,
+    Noise2D,
     
     // Api
     Rand,
+    Seed,
     Len,
+    PosX,
+    PosY,
+    Width,
+    Height,
     GetRed,
     GetGreen,
     GetBlue,
     Set,
+    GetColor,
+    SetColor,
+    SetXY,
+    FadeAll,
+    Fill,
+    Gradient,
+    Shift,
+    Rotate,
+    Mirror,
     Sleep,
+    Input,
+    Now,
+    Elapsed,
+    WaitFrame,
+
+    // Tasks
+    Spawn { relative_offset: i24 },
+    Exit,
 }
 
 static_assertions::const_assert_eq!(std::mem::size_of::<OpCode>(), 4);
@@ -179,6 +462,7 @@ impl fmt::Display for OpCode {
             OpCode::PushConstant { value } => write!(f, "PushConstant({})", Into::<i32>::into(*value)),
             OpCode::PushVariable { index } => write!(f, "PushVariable({})", index),
             OpCode::PopVariable { index } => write!(f, "PopVariable({})", index),
+            OpCode::ParamGet { index } => write!(f, "ParamGet({})", index),
             OpCode::Pop => write!(f, "Pop"),
             OpCode::Equal => write!(f, "Equal"),
             OpCode::NotEqual => write!(f, "NotEqual"),
@@ -195,13 +479,91 @@ impl fmt::Display for OpCode {
             OpCode::Div => write!(f, "Div"),
             OpCode::Pow => write!(f, "Pow"),
             OpCode::Mod => write!(f, "Mod"),
+            OpCode::Rgb => write!(f, "Rgb"),
+            OpCode::Hsv => write!(f, "Hsv"),
+            OpCode::Hsl => write!(f, "Hsl"),
+            OpCode::ColorRed => write!(f, "ColorRed"),
+            OpCode::ColorGreen => write!(f, "ColorGreen"),
+            OpCode::ColorBlue => write!(f, "ColorBlue"),
+            OpCode::Blend => write!(f, "Blend"),
+            OpCode::Scale => write!(f, "Scale"),
+            OpCode::AddSat => write!(f, "AddSat"),
+            OpCode::PaletteLookup { palette } => write!(f, "PaletteLookup({})", palette),
+            OpCode::Noise1D => write!(f, "Noise1D"),
+            OpCode::Noise2D => write!(f, "Noise2D"),
             OpCode::Rand => write!(f, "Rand"),
+            OpCode::Seed => write!(f, "Seed"),
             OpCode::Len => write!(f, "Len"),
+            OpCode::PosX => write!(f, "PosX"),
+            OpCode::PosY => write!(f, "PosY"),
+            OpCode::Width => write!(f, "Width"),
+            OpCode::Height => write!(f, "Height"),
             OpCode::GetRed => write!(f, "GetRed"),
             OpCode::GetGreen => write!(f, "GetGreen"),
             OpCode::GetBlue => write!(f, "GetBlue"),
             OpCode::Set => write!(f, "Set"),
+            OpCode::GetColor => write!(f, "GetColor"),
+            OpCode::SetColor => write!(f, "SetColor"),
+            OpCode::SetXY => write!(f, "SetXY"),
+            OpCode::FadeAll => write!(f, "FadeAll"),
+            OpCode::Fill => write!(f, "Fill"),
+            OpCode::Gradient => write!(f, "Gradient"),
+            OpCode::Shift => write!(f, "Shift"),
+            OpCode::Rotate => write!(f, "Rotate"),
+            OpCode::Mirror => write!(f, "Mirror"),
             OpCode::Sleep => write!(f, "Sleep"),
+            OpCode::Input => write!(f, "Input"),
+            OpCode::Now => write!(f, "Now"),
+            OpCode::Elapsed => write!(f, "Elapsed"),
+            OpCode::WaitFrame => write!(f, "WaitFrame"),
+            OpCode::Spawn { relative_offset } => write!(f, "Spawn({})", Into::<i32>::into(*relative_offset)),
+            OpCode::Exit => write!(f, "Exit"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // offsets of the values written by to_raw
+    const PARAMETERS_COUNT: usize = 16;
+    const PARAMETER_NAME_LEN: usize = 20;
+
+    fn raw() -> Box<[u8]> {
+        let parameter = ParameterDefinition { name: "speed".to_string(), default: 1, min: 0, max: 10 };
+        let entry_point = EntryPoint { event: Event::Timer { period: 100 }, address: 0 };
+
+        Executable::new(16, 2, vec![parameter], vec![entry_point], DebugInfo::default(), Vec::new()).to_raw()
+    }
+
+    // with a valid CRC, so that only the value is wrong
+    fn corrupt(raw: &[u8], offset: usize, value: u32) -> Vec<u8> {
+        let mut raw = raw.to_vec();
+        raw[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        let crc = Executable::compute_crc(&raw);
+        raw[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        raw
+    }
+
+    #[test]
+    fn round_trip() {
+        let exec = Executable::from_raw(&raw()).unwrap();
+
+        assert_eq!(exec.parameters()[0].name, "speed");
+        assert_eq!(exec.entry_points()[0].event, Event::Timer { period: 100 });
+    }
+
+    #[test]
+    fn rejects_huge_lengths() {
+        let raw = raw();
+
+        for offset in [PARAMETERS_COUNT, PARAMETER_NAME_LEN] {
+            let error = Executable::from_raw(&corrupt(&raw, offset, u32::MAX)).err().unwrap();
+            assert!(error.to_string().starts_with("Invalid length"), "{}", error);
+        }
+
+        assert!(Executable::from_raw(&raw[..raw.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,39 @@
+use std::{sync::Arc, time::Duration};
+
+use super::clock::Clock;
+
+// Time as seen by the program: it only moves between ticks, so all tasks of a tick see the same time
+pub struct FrameClock {
+    clock: Arc<dyn Clock>,
+    start: Duration,
+    current: Duration,
+    previous: Duration,
+}
+
+impl FrameClock {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+
+        Self {
+            clock,
+            start: now,
+            current: now,
+            previous: now,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.previous = self.current;
+        self.current = self.clock.now();
+    }
+
+    // time since the program started
+    pub fn now(&self) -> Duration {
+        self.current.saturating_sub(self.start)
+    }
+
+    // time since the previous frame
+    pub fn elapsed(&self) -> Duration {
+        self.current.saturating_sub(self.previous)
+    }
+}
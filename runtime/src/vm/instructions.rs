@@ -1,7 +1,8 @@
 
 use std::time::Duration;
 
-use super::{i24::i24, Machine, OpCode};
+use super::{i24::i24, noise, palettes, Machine, OpCode};
+use crate::render::Color;
 use anyhow::Result;
 
 pub fn execute(machine: &mut Machine, opcode: OpCode) -> Result<()> {
@@ -10,6 +11,7 @@ pub fn execute(machine: &mut Machine, opcode: OpCode) -> Result<()> {
         OpCode::PushConstant { value } => push_constant(machine, value),
         OpCode::PushVariable { index } => push_variable(machine, index),
         OpCode::PopVariable { index } => pop_variable(machine, index),
+        OpCode::ParamGet { index } => param_get(machine, index),
         OpCode::Pop => pop(machine),
         OpCode::Equal => comparer(machine, |op1, op2| op1 == op2),
         OpCode::NotEqual => comparer(machine, |op1, op2| op1 != op2),
@@ -26,13 +28,45 @@ pub fn execute(machine: &mut Machine, opcode: OpCode) -> Result<()> {
         OpCode::Mod => arithmetic(machine, |op1, op2| op1 % op2),
         OpCode::Jump { relative_offset } => jump(machine, relative_offset),
         OpCode::JumpIf { relative_offset } => jump_if(machine, relative_offset),
+        OpCode::Rgb => rgb(machine),
+        OpCode::Hsv => hsv(machine),
+        OpCode::Hsl => hsl(machine),
+        OpCode::ColorRed => color_channel(machine, Color::red),
+        OpCode::ColorGreen => color_channel(machine, Color::green),
+        OpCode::ColorBlue => color_channel(machine, Color::blue),
+        OpCode::Blend => blend(machine),
+        OpCode::Scale => scale(machine),
+        OpCode::AddSat => add_sat(machine),
+        OpCode::PaletteLookup { palette } => palette_lookup(machine, palette),
+        OpCode::Noise1D => noise1d(machine),
+        OpCode::Noise2D => noise2d(machine),
         OpCode::Rand => rand(machine),
+        OpCode::Seed => seed(machine),
         OpCode::Len => len(machine),
+        OpCode::PosX => pos_x(machine),
+        OpCode::PosY => pos_y(machine),
+        OpCode::Width => width(machine),
+        OpCode::Height => height(machine),
         OpCode::GetRed => get_red(machine),
         OpCode::GetGreen => get_green(machine),
         OpCode::GetBlue => get_blue(machine),
         OpCode::Set => set(machine),
+        OpCode::GetColor => get_color(machine),
+        OpCode::SetColor => set_color(machine),
+        OpCode::SetXY => set_xy(machine),
+        OpCode::FadeAll => fade_all(machine),
+        OpCode::Fill => fill(machine),
+        OpCode::Gradient => gradient(machine),
+        OpCode::Shift => shift(machine),
+        OpCode::Rotate => rotate(machine),
+        OpCode::Mirror => mirror(machine),
         OpCode::Sleep => sleep(machine),
+        OpCode::Input => input(machine),
+        OpCode::Now => now(machine),
+        OpCode::Elapsed => elapsed(machine),
+        OpCode::WaitFrame => wait_frame(machine),
+        OpCode::Spawn { relative_offset } => spawn(machine, relative_offset),
+        OpCode::Exit => exit(machine),
     }
 }
 
@@ -56,6 +90,13 @@ fn pop_variable(machine: &mut Machine, index: u8) -> Result<()> {
     Ok(())
 }
 
+fn param_get(machine: &mut Machine, index: u8) -> Result<()> {
+    let value = machine.parameters().get(index as usize)?;
+    machine.push(value)?;
+
+    Ok(())
+}
+
 fn pop(machine: &mut Machine) -> Result<()> {
     machine.pop()?;
 
@@ -137,17 +178,138 @@ fn jump_if(machine: &mut Machine, relative_offset: i24) -> Result<()> {
     Ok(())
 }
 
+fn rgb(machine: &mut Machine) -> Result<()> {
+    let blue = pop_channel(machine, "Blue")?;
+    let green = pop_channel(machine, "Green")?;
+    let red = pop_channel(machine, "Red")?;
+
+    push_color(machine, Color::from_rgb(red, green, blue))
+}
+
+fn hsv(machine: &mut Machine) -> Result<()> {
+    let value = pop_channel(machine, "Value")?;
+    let saturation = pop_channel(machine, "Saturation")?;
+    let hue = pop_hue(machine)?;
+
+    push_color(machine, Color::from_hsv(hue, saturation, value))
+}
+
+fn hsl(machine: &mut Machine) -> Result<()> {
+    let lightness = pop_channel(machine, "Lightness")?;
+    let saturation = pop_channel(machine, "Saturation")?;
+    let hue = pop_hue(machine)?;
+
+    push_color(machine, Color::from_hsl(hue, saturation, lightness))
+}
+
+fn color_channel(machine: &mut Machine, channel: fn(&Color) -> u8) -> Result<()> {
+    let color = pop_color(machine)?;
+
+    machine.push(channel(&color) as i32)?;
+
+    Ok(())
+}
+
+fn blend(machine: &mut Machine) -> Result<()> {
+    let amount = pop_channel(machine, "Amount")?;
+    let color2 = pop_color(machine)?;
+    let color1 = pop_color(machine)?;
+
+    push_color(machine, color1.blend(&color2, amount))
+}
+
+fn scale(machine: &mut Machine) -> Result<()> {
+    let amount = pop_channel(machine, "Amount")?;
+    let color = pop_color(machine)?;
+
+    push_color(machine, color.scale(amount))
+}
+
+fn add_sat(machine: &mut Machine) -> Result<()> {
+    let color2 = pop_color(machine)?;
+    let color1 = pop_color(machine)?;
+
+    push_color(machine, color1.add_saturating(&color2))
+}
+
+fn palette_lookup(machine: &mut Machine, palette: u8) -> Result<()> {
+    let position = pop_channel(machine, "Position")?;
+
+    let palette = palettes::get(palette as usize)
+        .ok_or_else(|| anyhow::anyhow!("Invalid palette index: {}", palette))?;
+
+    push_color(machine, palette.lookup(position))
+}
+
+fn pop_channel(machine: &mut Machine, name: &str) -> Result<u8> {
+    let value = machine.pop()?;
+
+    if !(0..=255).contains(&value) {
+        anyhow::bail!("Runtime error: {} must be in the range 0-255", name);
+    }
+
+    Ok(value as u8)
+}
+
+fn pop_hue(machine: &mut Machine) -> Result<u16> {
+    // hue is an angle, so any value wraps around
+    let hue = machine.pop()?;
+
+    Ok(hue.rem_euclid(360) as u16)
+}
+
+fn pop_color(machine: &mut Machine) -> Result<Color> {
+    let value = machine.pop()?;
+
+    if !(0..=0xFFFFFF).contains(&value) {
+        anyhow::bail!("Runtime error: Invalid color: {}", value);
+    }
+
+    Ok(Color::from_packed(value as u32))
+}
+
+fn push_color(machine: &mut Machine, color: Color) -> Result<()> {
+    machine.push(color.packed() as i32)
+}
+
+fn noise1d(machine: &mut Machine) -> Result<()> {
+    let seed = machine.pop()?;
+    let x = machine.pop()?;
+
+    machine.push(noise::noise1d(x, seed))?;
+
+    Ok(())
+}
+
+fn noise2d(machine: &mut Machine) -> Result<()> {
+    let seed = machine.pop()?;
+    let y = machine.pop()?;
+    let x = machine.pop()?;
+
+    machine.push(noise::noise2d(x, y, seed))?;
+
+    Ok(())
+}
+
 fn rand(machine: &mut Machine) -> Result<()> {
     let max = machine.pop()?;
     let min = machine.pop()?;
 
-    let result = machine.external_api().rand(min, max);
+    let result = machine.random_mut().range(min, max);
 
     machine.push(result)?;
 
     Ok(())
 }
 
+fn seed(machine: &mut Machine) -> Result<()> {
+    let seed = machine.pop()?;
+
+    machine.random_mut().reseed(seed as u32 as u64);
+
+    Ok(())
+}
+
 fn len(machine: &mut Machine) -> Result<()> {
     let result = machine.external_api().len();
 
@@ -156,14 +318,46 @@ fn len(machine: &mut Machine) -> Result<()> {
     Ok(())
 }
 
-fn get_red(machine: &mut Machine) -> Result<()> {
-    let index = machine.pop()?;
+fn pos_x(machine: &mut Machine) -> Result<()> {
+    let index = pop_index(machine)?;
 
-    if index < 0 {
-        anyhow::bail!("Runtime error: Index must be non-negative");
-    }
+    let (x, _y) = machine.external_api().position(index);
+
+    machine.push(x)?;
 
-    let (red, _green, _blue) = machine.external_api().get(index as usize);
+    Ok(())
+}
+
+fn pos_y(machine: &mut Machine) -> Result<()> {
+    let index = pop_index(machine)?;
+
+    let (_x, y) = machine.external_api().position(index);
+
+    machine.push(y)?;
+
+    Ok(())
+}
+
+fn width(machine: &mut Machine) -> Result<()> {
+    let result = machine.external_api().width();
+
+    machine.push(result)?;
+
+    Ok(())
+}
+
+fn height(machine: &mut Machine) -> Result<()> {
+    let result = machine.external_api().height();
+
+    machine.push(result)?;
+
+    Ok(())
+}
+
+fn get_red(machine: &mut Machine) -> Result<()> {
+    let index = pop_index(machine)?;
+
+    let (red, _green, _blue) = machine.external_api().get(index);
 
     machine.push(red as i32)?;
 
@@ -171,13 +365,9 @@ fn get_red(machine: &mut Machine) -> Result<()> {
 }
 
 fn get_green(machine: &mut Machine) -> Result<()> {
-    let index = machine.pop()?;
+    let index = pop_index(machine)?;
 
-    if index < 0 {
-        anyhow::bail!("Runtime error: Index must be non-negative");
-    }
-
-    let (_red, green, _blue) = machine.external_api().get(index as usize);
+    let (_red, green, _blue) = machine.external_api().get(index);
 
     machine.push(green as i32)?;
 
@@ -185,13 +375,9 @@ fn get_green(machine: &mut Machine) -> Result<()> {
 }
 
 fn get_blue(machine: &mut Machine) -> Result<()> {
-    let index = machine.pop()?;
+    let index = pop_index(machine)?;
 
-    if index < 0 {
-        anyhow::bail!("Runtime error: Index must be non-negative");
-    }
-
-    let (_red, _green, blue) = machine.external_api().get(index as usize);
+    let (_red, _green, blue) = machine.external_api().get(index);
 
     machine.push(blue as i32)?;
 
@@ -202,11 +388,7 @@ fn set(machine: &mut Machine) -> Result<()> {
     let blue = machine.pop()?;
     let green = machine.pop()?;
     let red = machine.pop()?;
-    let index = machine.pop()?;
-
-    if index < 0 {
-        anyhow::bail!("Runtime error: Index must be non-negative");
-    }
+    let index = pop_index(machine)?;
 
     if red < 0 || red > 255 {
         anyhow::bail!("Runtime error: Red must be in the range 0-255");
@@ -220,15 +402,190 @@ fn set(machine: &mut Machine) -> Result<()> {
         anyhow::bail!("Runtime error: Red must be in the range 0-255");
     }
 
-    machine.external_api().set(index as usize, (red as u8, green as u8, blue as u8));
+    machine.external_api().set(index, (red as u8, green as u8, blue as u8));
 
     Ok(())
 }
 
+fn get_color(machine: &mut Machine) -> Result<()> {
+    let index = pop_index(machine)?;
+
+    let (red, green, blue) = machine.external_api().get(index);
+
+    push_color(machine, Color::from_rgb(red, green, blue))
+}
+
+fn set_color(machine: &mut Machine) -> Result<()> {
+    let color = pop_color(machine)?;
+    let index = pop_index(machine)?;
+
+    machine.external_api().set(index, (color.red(), color.green(), color.blue()));
+
+    Ok(())
+}
+
+fn set_xy(machine: &mut Machine) -> Result<()> {
+    let color = pop_color(machine)?;
+    let y = machine.pop()?;
+    let x = machine.pop()?;
+
+    let index = if x >= 0 && y >= 0 {
+        machine.external_api().matrix_index(x as usize, y as usize)
+    } else {
+        None
+    };
+
+    let Some(index) = index else {
+        anyhow::bail!("Runtime error: Position {},{} is not on the matrix (SetXY needs a matrix layout)", x, y);
+    };
+
+    machine.external_api().set(index, (color.red(), color.green(), color.blue()));
+
+    Ok(())
+}
+
+fn fade_all(machine: &mut Machine) -> Result<()> {
+    let amount = pop_channel(machine, "Amount")?;
+
+    machine.external_api().fade_all(amount);
+
+    Ok(())
+}
+
+fn fill(machine: &mut Machine) -> Result<()> {
+    let color = pop_color(machine)?;
+    let (start, end) = pop_range(machine)?;
+
+    machine.external_api().fill(start, end, (color.red(), color.green(), color.blue()));
+
+    Ok(())
+}
+
+fn gradient(machine: &mut Machine) -> Result<()> {
+    let color2 = pop_color(machine)?;
+    let color1 = pop_color(machine)?;
+    let (start, end) = pop_range(machine)?;
+
+    machine.external_api().gradient(
+        start,
+        end,
+        (color1.red(), color1.green(), color1.blue()),
+        (color2.red(), color2.green(), color2.blue()),
+    );
+
+    Ok(())
+}
+
+fn shift(machine: &mut Machine) -> Result<()> {
+    let offset = machine.pop()?;
+
+    machine.external_api().shift(offset as isize);
+
+    Ok(())
+}
+
+fn rotate(machine: &mut Machine) -> Result<()> {
+    let offset = machine.pop()?;
+
+    machine.external_api().rotate(offset as isize);
+
+    Ok(())
+}
+
+fn mirror(machine: &mut Machine) -> Result<()> {
+    let (start, end) = pop_range(machine)?;
+
+    machine.external_api().mirror(start, end);
+
+    Ok(())
+}
+
+fn pop_index(machine: &mut Machine) -> Result<usize> {
+    let index = machine.pop()?;
+    let len = machine.external_api().len() as i32;
+
+    if index < 0 || index >= len {
+        anyhow::bail!("Runtime error: Index {} must be within 0-{}", index, len - 1);
+    }
+
+    Ok(index as usize)
+}
+
+fn pop_range(machine: &mut Machine) -> Result<(usize, usize)> {
+    let end = machine.pop()?;
+    let start = machine.pop()?;
+    let len = machine.external_api().len() as i32;
+
+    if start < 0 || start > end || end >= len {
+        anyhow::bail!("Runtime error: Invalid range {}-{}, must be ordered and within 0-{}", start, end, len - 1);
+    }
+
+    Ok((start as usize, end as usize))
+}
+
 fn sleep(machine: &mut Machine) -> Result<()> {
     let duration = machine.pop()?;
+
+    if duration < 0 {
+        anyhow::bail!("Runtime error: Sleep duration must be non-negative");
+    }
+
     let duration = Duration::from_millis(duration as u64);
+
+    if duration > machine.max_sleep() {
+        anyhow::bail!("Runtime error: Sleep duration must be at most {}ms", machine.max_sleep().as_millis());
+    }
+
     machine.sleep(duration);
 
     Ok(())
 }
+
+fn input(machine: &mut Machine) -> Result<()> {
+    let channel = machine.pop()?;
+
+    if channel < 0 {
+        anyhow::bail!("Runtime error: Channel must be non-negative");
+    }
+
+    let result = machine.external_api().input(channel as u32);
+
+    machine.push(result)?;
+
+    Ok(())
+}
+
+fn now(machine: &mut Machine) -> Result<()> {
+    // milliseconds wrap after ~24 days
+    let result = machine.clock().now().as_millis() as i32;
+
+    machine.push(result)?;
+
+    Ok(())
+}
+
+fn elapsed(machine: &mut Machine) -> Result<()> {
+    let result = machine.clock().elapsed().as_millis() as i32;
+
+    machine.push(result)?;
+
+    Ok(())
+}
+
+fn wait_frame(machine: &mut Machine) -> Result<()> {
+    machine.wait_frame();
+
+    Ok(())
+}
+
+fn spawn(machine: &mut Machine, relative_offset: i24) -> Result<()> {
+    machine.spawn(relative_offset.into())?;
+
+    Ok(())
+}
+
+fn exit(machine: &mut Machine) -> Result<()> {
+    machine.exit();
+
+    Ok(())
+}
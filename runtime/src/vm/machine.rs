@@ -1,29 +1,39 @@
 use std::{sync::Arc, time::Duration};
 
-use super::{ExternalApi, OpCode};
+use super::{
+    clock::Clock, frame_clock::FrameClock, parameters::ParameterTable, random::Random, task::Task, ExternalApi, OpCode,
+};
 use anyhow::Result;
-use wasm_timer::SystemTime;
 
 pub struct Machine {
     locals: Box<[i32]>,
-    stack: Box<[i32]>,
-    stack_index: usize,
+    parameters: ParameterTable,
     instructions: Box<[OpCode]>,
-    instruction_index: usize,
     api: Arc<dyn ExternalApi>,
-    wakeup_time: SystemTime,
+    clock: FrameClock,
+    max_sleep: Duration,
+    random: Random,
+    stack_size: usize,
+    tasks: Vec<Task>,
+    current_task: usize,
 }
 
 impl Machine {
-    pub fn load_executable(exec: super::Executable, api: Arc<dyn ExternalApi>) -> Self {
+    const MAX_TASKS: usize = 32;
+
+    pub fn load_executable(exec: super::Executable, api: Arc<dyn ExternalApi>, clock: Arc<dyn Clock>, max_sleep: Duration, seed: u64) -> Self {
         Self {
             locals: vec![0; exec.locals_size()].into_boxed_slice(),
-            stack: vec![0; exec.stack_size()].into_boxed_slice(),
-            stack_index: 0,
+            parameters: ParameterTable::new(exec.parameters()),
             instructions: exec.code().into(),
-            instruction_index: 0,
             api,
-            wakeup_time: SystemTime::now(),
+            clock: FrameClock::new(clock),
+            max_sleep,
+            random: Random::new(seed),
+            stack_size: exec.stack_size(),
+            // main task starts at the beginning of the code
+            tasks: vec![Task::new(exec.stack_size(), 0, None, Duration::ZERO)],
+            current_task: 0,
         }
     }
 
@@ -44,52 +54,129 @@ impl Machine {
         Ok(())
     }
 
-    pub fn push(&mut self, value: i32) -> Result<()> {
-        if self.stack_index == self.stack.len() {
-            anyhow::bail!("Stack overflow");
-        }
+    pub fn locals(&self) -> &[i32] {
+        &self.locals
+    }
 
-        self.stack[self.stack_index] = value;
-        self.stack_index += 1;
+    pub fn parameters(&self) -> &ParameterTable {
+        &self.parameters
+    }
 
-        Ok(())
+    pub fn parameters_mut(&mut self) -> &mut ParameterTable {
+        &mut self.parameters
+    }
+
+    pub fn push(&mut self, value: i32) -> Result<()> {
+        self.task_mut().push(value)
     }
 
     pub fn pop(&mut self) -> Result<i32> {
-        if self.stack_index == 0 {
-            anyhow::bail!("Stack underflow");
-        }
+        self.task_mut().pop()
+    }
+
+    pub fn stack(&self) -> &[i32] {
+        self.task().stack()
+    }
 
-        self.stack_index -= 1;
-        Ok(self.stack[self.stack_index])
+    pub fn instruction_index(&self) -> usize {
+        self.task().instruction_index()
     }
 
     pub fn fetch_instruction(&mut self) -> Result<OpCode> {
+        let instruction_index = self.task().instruction_index();
         let instruction = self
             .instructions
-            .get(self.instruction_index)
+            .get(instruction_index)
             .copied()
             .ok_or_else(|| {
-                anyhow::anyhow!("Invalid instruction index: {}", self.instruction_index)
+                anyhow::anyhow!("Invalid instruction index: {}", instruction_index)
             })?;
-        self.instruction_index += 1;
+        self.task_mut().set_instruction_index(instruction_index + 1);
         Ok(instruction)
     }
 
     pub fn jump(&mut self, relative_offset: i32) -> Result<()> {
+        let new_index = self.resolve_jump(relative_offset)?;
+        self.task_mut().set_instruction_index(new_index);
+        Ok(())
+    }
+
+    pub fn spawn(&mut self, relative_offset: i32) -> Result<()> {
+        if self.tasks.len() == Self::MAX_TASKS {
+            anyhow::bail!("Too many tasks! Maximum is {}.", Self::MAX_TASKS);
+        }
+
+        let start_index = self.resolve_jump(relative_offset)?;
+        self.tasks.push(Task::new(self.stack_size, start_index, None, self.clock.now()));
+        Ok(())
+    }
+
+    pub fn spawn_handler(&mut self, entry_point: usize, address: usize) -> Result<()> {
+        if self.tasks.len() == Self::MAX_TASKS {
+            anyhow::bail!("Too many tasks! Maximum is {}.", Self::MAX_TASKS);
+        }
+
+        if address >= self.instructions.len() {
+            anyhow::bail!("Invalid entry point address: {}", address);
+        }
+
+        self.tasks.push(Task::new(self.stack_size, address, Some(entry_point), self.clock.now()));
+        Ok(())
+    }
+
+    pub fn handler_running(&self, entry_point: usize) -> bool {
+        self.tasks
+            .iter()
+            .any(|task| !task.exited() && task.entry_point() == Some(entry_point))
+    }
+
+    fn resolve_jump(&self, relative_offset: i32) -> Result<usize> {
         // instruction_index points to the next instruction, but relative offset is relative to the current instruction
-        let new_index = self.instruction_index as i32 - 1 + relative_offset;
+        let new_index = self.task().instruction_index() as i32 - 1 + relative_offset;
         if new_index < 0 || new_index as usize >= self.instructions.len() {
             anyhow::bail!("Invalid jump target: {}", new_index);
         }
 
-        self.instruction_index = new_index as usize;
-        Ok(())
+        Ok(new_index as usize)
     }
 
     pub fn sleep(&mut self, duration: Duration) {
         // debug!("Sleeping for {:?}", duration);
-        self.wakeup_time = SystemTime::now() + duration;
+        let now = self.clock.now();
+        self.task_mut().sleep(duration, now);
+    }
+
+    pub fn max_sleep(&self) -> Duration {
+        self.max_sleep
+    }
+
+    pub fn set_max_sleep(&mut self, max_sleep: Duration) {
+        self.max_sleep = max_sleep;
+    }
+
+    pub fn random_mut(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    pub fn wait_frame(&mut self) {
+        self.task_mut().wait_frame();
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.clock.tick();
+
+        let now = self.clock.now();
+        for task in self.tasks.iter_mut() {
+            task.end_wait_frame(now);
+        }
+    }
+
+    pub fn clock(&self) -> &FrameClock {
+        &self.clock
+    }
+
+    pub fn exit(&mut self) {
+        self.task_mut().exit();
     }
 
     pub fn external_api(&self) -> &dyn ExternalApi {
@@ -97,7 +184,39 @@ impl Machine {
     }
 
     pub fn sleeping(&self) -> bool {
-        self.wakeup_time > SystemTime::now()
+        self.task().sleeping(self.clock.now())
     }
 
+    pub fn exited(&self) -> bool {
+        self.task().exited()
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn current_task(&self) -> usize {
+        self.current_task
+    }
+
+    pub fn select_task(&mut self, index: usize) {
+        self.current_task = index;
+    }
+
+    pub fn remove_exited_tasks(&mut self) {
+        self.tasks.retain(|task| !task.exited());
+        self.current_task = 0;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    fn task(&self) -> &Task {
+        &self.tasks[self.current_task]
+    }
+
+    fn task_mut(&mut self) -> &mut Task {
+        &mut self.tasks[self.current_task]
+    }
 }
@@ -1,36 +1,85 @@
+pub mod clock;
 pub mod executable;
 pub mod i24;
+pub mod palettes;
+pub mod trace;
+mod debugger;
+mod events;
+mod frame_clock;
 mod machine;
+mod noise;
 mod instructions;
+mod parameters;
+mod random;
+mod task;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
-use executable::{Executable, OpCode};
+use clock::Clock;
+use debugger::Breakpoints;
+pub use debugger::DebugState;
+use events::EventDispatcher;
+use executable::{DebugInfo, Executable, OpCode};
 use log::{error, info};
 use machine::Machine;
+pub use parameters::ParameterInfo;
 
 pub trait ExternalApi : Sync + Send {
-    fn rand(&self, min: i32, max: i32) -> i32;
-
     fn len(&self) -> usize;
+    fn position(&self, index: usize) -> (i32, i32);
+    fn width(&self) -> i32;
+    fn height(&self) -> i32;
+    // None if the layout is not a matrix or if the coordinates are outside of it
+    fn matrix_index(&self, x: usize, y: usize) -> Option<usize>;
     fn get(&self, index: usize) -> (u8, u8, u8);
     fn set(&self, index: usize, color: (u8, u8, u8));
+    fn fade_all(&self, amount: u8);
+    fn fill(&self, start: usize, end: usize, color: (u8, u8, u8));
+    fn gradient(&self, start: usize, end: usize, color1: (u8, u8, u8), color2: (u8, u8, u8));
+    fn shift(&self, offset: isize);
+    fn rotate(&self, offset: isize);
+    fn mirror(&self, start: usize, end: usize);
+
+    fn input(&self, channel: u32) -> i32;
 }
 
 pub struct VM {
     api: Arc<dyn ExternalApi>,
+    clock: Arc<dyn Clock>,
+    max_sleep: Duration,
+    breakpoints: Breakpoints,
     state: State,
 }
 
 impl VM {
-    pub fn new(api: Box<dyn ExternalApi>) -> Self {
+    const DEFAULT_MAX_SLEEP: Duration = Duration::from_secs(60);
+
+    pub fn new(api: Box<dyn ExternalApi>, clock: Box<dyn Clock>) -> Self {
         Self { 
             api: Arc::from(api),
+            clock: Arc::from(clock),
+            max_sleep: Self::DEFAULT_MAX_SLEEP,
+            breakpoints: Breakpoints::new(),
             state: State::new(),
         }
     }
 
+    // swap the outside world of the VM (eg: to record or replay a trace), the running program is stopped
+    pub fn set_io(&mut self, api: Box<dyn ExternalApi>, clock: Box<dyn Clock>) {
+        self.state.stop();
+        self.api = Arc::from(api);
+        self.clock = Arc::from(clock);
+    }
+
+    pub fn set_max_sleep(&mut self, max_sleep: Duration) {
+        self.max_sleep = max_sleep;
+
+        if let State::Running(state) = &mut self.state {
+            state.machine.set_max_sleep(max_sleep);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.state.stop();
     }
@@ -39,32 +88,97 @@ impl VM {
         self.state.running()
     }
 
-    pub fn load_executable(&mut self, exec: Executable) {
-        info!("Loading executable: {}", exec);
+    // the seed makes `Rand` deterministic: the same program with the same seed always draws the same values
+    pub fn load_executable(&mut self, exec: Executable, seed: u64) {
+        info!("Loading executable (seed={}): {}", seed, exec);
 
-        let machine = Machine::load_executable(exec, self.api.clone());
-        self.state.start(machine);
+        let dispatcher = EventDispatcher::new(exec.entry_points());
+        let debug_info = exec.debug_info().clone();
+        let machine = Machine::load_executable(exec, self.api.clone(), self.clock.clone(), self.max_sleep, seed);
+        self.state.start(machine, dispatcher, debug_info);
+
+        self.resolve_breakpoints();
     }
 
-    pub fn tick(&mut self) {
+    pub fn parameters(&self) -> Vec<ParameterInfo> {
+        match &self.state {
+            State::Running(state) => state.machine.parameters().list(),
+            State::Stopped => Vec::new(),
+        }
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: i32) -> Result<()> {
         match &mut self.state {
-            State::Running(state) => {
-                match state.tick() {
-                    Ok(()) => {}
-                    Err(e) => {
-                        error!("{}", e);
-                        self.state.stop();
-                    }
-                }
-            }
-            State::Stopped => {}
+            State::Running(state) => state.machine.parameters_mut().set(name, value),
+            State::Stopped => anyhow::bail!("No program running"),
         }
     }
 
+    pub fn input_changed(&mut self, channel: u32) {
+        if let State::Running(state) = &mut self.state {
+            state.dispatcher.input_changed(channel);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.state.run(|state| state.tick(&self.breakpoints));
+    }
+
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints.add_address(address);
+        self.resolve_breakpoints();
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) {
+        self.breakpoints.remove_address(address);
+        self.resolve_breakpoints();
+    }
+
+    pub fn set_block_breakpoint(&mut self, id: &str) {
+        self.breakpoints.add_block(id);
+        self.resolve_breakpoints();
+    }
+
+    pub fn remove_block_breakpoint(&mut self, id: &str) {
+        self.breakpoints.remove_block(id);
+        self.resolve_breakpoints();
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn debug_continue(&mut self) {
+        self.state.run(|state| state.resume());
+    }
+
+    pub fn debug_step_instruction(&mut self) {
+        self.state.run(|state| state.step_instruction());
+    }
+
+    pub fn debug_step_statement(&mut self) {
+        self.state.run(|state| state.step_statement());
+    }
+
+    pub fn debug_state(&self) -> Option<DebugState> {
+        match &self.state {
+            State::Running(state) => Some(state.debug_state()),
+            State::Stopped => None,
+        }
+    }
+
+    fn resolve_breakpoints(&mut self) {
+        let debug_info = match &self.state {
+            State::Running(state) => Some(&state.debug_info),
+            State::Stopped => None,
+        };
+
+        self.breakpoints.resolve(debug_info);
+    }
 }
 
 enum State {
-    Running(RunningState),
+    Running(Box<RunningState>),
     Stopped,
 }
 
@@ -80,17 +194,42 @@ impl State {
         }
     }
 
-    pub fn start(&mut self, machine: Machine) {
-        *self = State::Running(RunningState::new(machine));
+    pub fn start(&mut self, machine: Machine, dispatcher: EventDispatcher, debug_info: DebugInfo) {
+        *self = State::Running(Box::new(RunningState::new(machine, dispatcher, debug_info)));
     }
 
     pub fn stop(&mut self) {
         *self = State::Stopped;
     }
+
+    pub fn run<F: FnOnce(&mut RunningState) -> Result<()>>(&mut self, f: F) {
+        match self {
+            State::Running(state) => {
+                match f(state) {
+                    Ok(()) => {
+                        if state.finished() {
+                            self.stop();
+                        }
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        self.stop();
+                    }
+                }
+            }
+            State::Stopped => {}
+        }
+    }
 }
 
 struct RunningState {
     machine: Machine,
+    dispatcher: EventDispatcher,
+    debug_info: DebugInfo,
+    // stopped on a breakpoint, only the debugger can make progress
+    paused: bool,
+    // task to continue with when a frame was interrupted by a breakpoint, None between frames
+    resume_task: Option<usize>,
 }
 
 impl Drop for RunningState {
@@ -100,27 +239,161 @@ impl Drop for RunningState {
 }
 
 impl RunningState {
-    pub fn new(machine: Machine) -> Self {
+    pub fn new(machine: Machine, dispatcher: EventDispatcher, debug_info: DebugInfo) -> Self {
         info!("VM started");
-        Self { machine }
+        Self {
+            machine,
+            dispatcher,
+            debug_info,
+            paused: false,
+            resume_task: None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.machine.finished() && self.dispatcher.idle()
+    }
+
+    pub fn tick(&mut self, breakpoints: &Breakpoints) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+
+        // finish the frame interrupted by a breakpoint before starting a new one
+        let mut index = match self.resume_task.take() {
+            Some(index) => index,
+            None => {
+                self.begin_frame()?;
+                0
+            }
+        };
+
+        // tasks spawned during this tick are appended, so they get to run in the same tick
+        while index < self.machine.task_count() {
+            self.machine.select_task(index);
+            self.run_task(breakpoints)?;
+
+            if self.paused {
+                // keep the current task selected for the debugger, it and the next ones will run once resumed
+                self.resume_task = Some(index);
+                return Ok(());
+            }
+
+            index += 1;
+        }
+
+        self.machine.remove_exited_tasks();
+
+        Ok(())
     }
 
-    pub fn tick(&mut self) -> Result<()> {
+    fn begin_frame(&mut self) -> Result<()> {
+        self.machine.begin_frame();
+
+        // handlers are only started between task runs, so they never preempt another task
+        self.dispatcher.dispatch(&mut self.machine)
+    }
+
+    // for the debugger: select the interrupted task, or the next one that can run
+    // if the frame is over, a new one is started, returns false if no task can run yet
+    fn select_runnable_task(&mut self) -> Result<bool> {
+        let mut index = self.resume_task.unwrap_or(usize::MAX);
+
+        for new_frame in [false, true] {
+            if new_frame {
+                self.machine.remove_exited_tasks();
+                self.begin_frame()?;
+                index = 0;
+            }
+
+            while index < self.machine.task_count() {
+                self.machine.select_task(index);
+
+                if !self.machine.sleeping() && !self.machine.exited() {
+                    self.resume_task = Some(index);
+                    return Ok(true);
+                }
+
+                index += 1;
+            }
+        }
+
+        // all tasks are waiting, the next tick starts a new frame
+        self.resume_task = None;
+        Ok(false)
+    }
+
+    fn run_task(&mut self, breakpoints: &Breakpoints) -> Result<()> {
         let mut loop_guard = LoopGuard::new();
-        
+
         loop {
-            if self.machine.sleeping() {
+            if self.machine.sleeping() || self.machine.exited() {
                 break;
             }
 
+            if breakpoints.contains(self.machine.instruction_index()) {
+                info!("Breakpoint hit at {}", self.machine.instruction_index());
+                self.paused = true;
+                break;
+            }
+
+            loop_guard.next()?;
+            self.execute_next()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+
+        // move past the breakpoint we are stopped on, so we do not hit it again right away
+        self.step_instruction()?;
+        self.paused = false;
+
+        Ok(())
+    }
+
+    pub fn step_instruction(&mut self) -> Result<()> {
+        if !self.paused || !self.select_runnable_task()? {
+            return Ok(());
+        }
+
+        self.execute_next()
+    }
+
+    pub fn step_statement(&mut self) -> Result<()> {
+        if !self.paused || !self.select_runnable_task()? {
+            return Ok(());
+        }
+
+        let mut loop_guard = LoopGuard::new();
+
+        loop {
             loop_guard.next()?;
+            self.execute_next()?;
 
-            let opcode = self.machine.fetch_instruction()?;
-            instructions::execute(&mut self.machine, opcode)?;
+            if self.machine.sleeping()
+                || self.machine.exited()
+                || self.debug_info.is_statement_start(self.machine.instruction_index())
+            {
+                break;
+            }
         }
 
         Ok(())
     }
+
+    pub fn debug_state(&self) -> DebugState {
+        DebugState::capture(&self.machine, &self.debug_info, self.paused)
+    }
+
+    fn execute_next(&mut self) -> Result<()> {
+        let opcode = self.machine.fetch_instruction()?;
+        instructions::execute(&mut self.machine, opcode)
+    }
 }
 
 struct LoopGuard {
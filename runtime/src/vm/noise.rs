@@ -0,0 +1,130 @@
+// Perlin gradient noise, computed with integers only so that every platform gives the same values.
+// Coordinates are fixed-point with 8 fractional bits: the lattice has one point every 256 units.
+// Results are in 0-255.
+
+const ONE: i64 = 1 << 16;
+
+pub fn noise1d(x: i32, seed: i32) -> i32 {
+    let (xi, tx) = split(x);
+
+    let d0 = (gradient1d(xi, seed) * tx) >> 16;
+    let d1 = (gradient1d(xi.wrapping_add(1), seed) * (tx - ONE)) >> 16;
+
+    // 1D noise stays within [-0.5, 0.5]
+    to_byte(lerp(d0, d1, fade(tx)) * 2)
+}
+
+pub fn noise2d(x: i32, y: i32, seed: i32) -> i32 {
+    let (xi, tx) = split(x);
+    let (yi, ty) = split(y);
+    let xj = xi.wrapping_add(1);
+    let yj = yi.wrapping_add(1);
+
+    let d00 = gradient2d(xi, yi, seed, tx, ty);
+    let d10 = gradient2d(xj, yi, seed, tx - ONE, ty);
+    let d01 = gradient2d(xi, yj, seed, tx, ty - ONE);
+    let d11 = gradient2d(xj, yj, seed, tx - ONE, ty - ONE);
+
+    let u = fade(tx);
+    let v = fade(ty);
+
+    to_byte(lerp(lerp(d00, d10, u), lerp(d01, d11, u), v))
+}
+
+// (lattice cell, position inside the cell in 16.16)
+fn split(coord: i32) -> (i32, i64) {
+    (coord >> 8, ((coord & 0xFF) as i64) << 8)
+}
+
+// 6t^5 - 15t^4 + 10t^3
+fn fade(t: i64) -> i64 {
+    let t3 = (((t * t) >> 16) * t) >> 16;
+    (t3 * (((t * (t * 6 - 15 * ONE)) >> 16) + 10 * ONE)) >> 16
+}
+
+fn lerp(a: i64, b: i64, t: i64) -> i64 {
+    a + (((b - a) * t) >> 16)
+}
+
+fn to_byte(value: i64) -> i32 {
+    (128 + ((value * 127) >> 16)).clamp(0, 255) as i32
+}
+
+// slope in [-1, 1]
+fn gradient1d(xi: i32, seed: i32) -> i64 {
+    let hash = hash(xi as u32 ^ hash(seed as u32));
+    (hash & 0x1FFFF) as i64 - ONE
+}
+
+fn gradient2d(xi: i32, yi: i32, seed: i32, dx: i64, dy: i64) -> i64 {
+    let hash = hash(xi as u32 ^ hash(yi as u32 ^ hash(seed as u32)));
+
+    // 8 directions, diagonals are not normalized as in the reference implementation
+    match hash & 7 {
+        0 => dx,
+        1 => -dx,
+        2 => dy,
+        3 => -dy,
+        4 => dx + dy,
+        5 => -dx + dy,
+        6 => dx - dy,
+        _ => -dx - dy,
+    }
+}
+
+// lowbias32, see https://nullprogram.com/blog/2018/07/31/
+fn hash(mut value: u32) -> u32 {
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846ca68b);
+    value ^= value >> 16;
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise1d_values() {
+        let values: Vec<i32> = (0..8).map(|i| noise1d(i * 100, 0)).collect();
+        assert_eq!(values, vec![128, 32, 84, 150, 150, 126, 102, 76]);
+        let values: Vec<i32> = (0..8).map(|i| noise1d(i * 100, 1234)).collect();
+        assert_eq!(values, vec![128, 80, 133, 107, 67, 120, 198, 190]);
+    }
+
+    #[test]
+    fn noise2d_values() {
+        let values: Vec<i32> = (0..8).map(|i| noise2d(i * 100, i * 37, 0)).collect();
+        assert_eq!(values, vec![128, 136, 118, 176, 158, 123, 165, 137]);
+        let values: Vec<i32> = (0..8).map(|i| noise2d(i * 100, -i * 37, 1234)).collect();
+        assert_eq!(values, vec![128, 179, 143, 98, 118, 150, 88, 86]);
+    }
+
+    #[test]
+    fn lattice_points_are_neutral() {
+        for i in -4..4 {
+            assert_eq!(noise1d(i * 256, 42), 128);
+            assert_eq!(noise2d(i * 256, i * 512, 42), 128);
+        }
+    }
+
+    #[test]
+    fn output_range() {
+        for seed in 0..4 {
+            for x in (-2000..2000).step_by(7) {
+                assert!((0..=255).contains(&noise1d(x, seed)));
+                assert!((0..=255).contains(&noise2d(x, x * 3 + 11, seed)));
+            }
+        }
+    }
+
+    #[test]
+    fn continuity() {
+        for x in -1000..1000 {
+            assert!((noise1d(x, 7) - noise1d(x + 1, 7)).abs() <= 2);
+            assert!((noise2d(x, 300, 7) - noise2d(x + 1, 300, 7)).abs() <= 2);
+        }
+    }
+}
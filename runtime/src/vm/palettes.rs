@@ -0,0 +1,67 @@
+use crate::render::Color;
+
+pub struct Palette {
+    pub name: &'static str,
+    // packed 0xRRGGBB, evenly spread over the 0-255 positions
+    pub colors: &'static [u32],
+}
+
+pub const PALETTES: &[Palette] = &[
+    Palette {
+        name: "rainbow",
+        colors: &[0xFF0000, 0xFF7F00, 0xFFFF00, 0x00FF00, 0x0000FF, 0x4B0082, 0x8B00FF],
+    },
+    Palette {
+        name: "christmas",
+        colors: &[0xC00000, 0xFFFFFF, 0x00A000, 0xFFD700, 0xC00000],
+    },
+    Palette {
+        name: "ocean",
+        colors: &[0x000040, 0x00008B, 0x0060C0, 0x00C0C0, 0x7FFFD4, 0xFFFFFF],
+    },
+    Palette {
+        name: "fire",
+        colors: &[0x000000, 0x800000, 0xFF0000, 0xFF8000, 0xFFFF00, 0xFFFFFF],
+    },
+    Palette {
+        name: "forest",
+        colors: &[0x003000, 0x006400, 0x228B22, 0x6B8E23, 0x9ACD32, 0x556B2F],
+    },
+    Palette {
+        name: "lava",
+        colors: &[0x000000, 0x400000, 0xC00000, 0xFF4000, 0xFFA000, 0xFFFF80],
+    },
+    Palette {
+        name: "ice",
+        colors: &[0xFFFFFF, 0xC0E0FF, 0x80C0FF, 0x4080FF, 0x0040C0],
+    },
+    Palette {
+        name: "party",
+        colors: &[0x5500AB, 0x84007C, 0xB5004B, 0xE5001B, 0xE81700, 0xB84700, 0xAB7700, 0xABAB00],
+    },
+];
+
+pub fn find(name: &str) -> Option<usize> {
+    PALETTES.iter().position(|palette| palette.name == name)
+}
+
+pub fn get(index: usize) -> Option<&'static Palette> {
+    PALETTES.get(index)
+}
+
+impl Palette {
+    // interpolate between the two entries around the position
+    pub fn lookup(&self, position: u8) -> Color {
+        let last = self.colors.len() - 1;
+        let scaled = position as usize * last;
+        let index = scaled / 255;
+
+        let color = Color::from_packed(self.colors[index]);
+        if index == last {
+            return color;
+        }
+
+        let next = Color::from_packed(self.colors[index + 1]);
+        color.blend(&next, (scaled % 255) as u8)
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use super::executable::ParameterDefinition;
+
+pub struct ParameterTable {
+    definitions: Vec<ParameterDefinition>,
+    values: Box<[i32]>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterInfo {
+    #[serde(flatten)]
+    pub definition: ParameterDefinition,
+    pub value: i32,
+}
+
+impl ParameterTable {
+    pub fn new(definitions: &[ParameterDefinition]) -> Self {
+        Self {
+            definitions: definitions.to_vec(),
+            values: definitions.iter().map(|def| def.default).collect(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Result<i32> {
+        let value = self
+            .values
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Invalid parameter index: {}", index))?;
+        Ok(*value)
+    }
+
+    pub fn set(&mut self, name: &str, value: i32) -> Result<()> {
+        let index = self
+            .definitions
+            .iter()
+            .position(|def| def.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Parameter not found: {}", name))?;
+
+        let def = &self.definitions[index];
+        if !(def.min..=def.max).contains(&value) {
+            anyhow::bail!("Parameter {} must be in the range {}-{}", name, def.min, def.max);
+        }
+
+        self.values[index] = value;
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<ParameterInfo> {
+        self.definitions
+            .iter()
+            .zip(self.values.iter())
+            .map(|(definition, value)| ParameterInfo {
+                definition: definition.clone(),
+                value: *value,
+            })
+            .collect()
+    }
+}
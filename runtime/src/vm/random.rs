@@ -0,0 +1,111 @@
+// PCG32 (XSH RR variant), see https://www.pcg-random.org
+pub struct Random {
+    state: u64,
+    increment: u64,
+}
+
+impl Random {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const DEFAULT_INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut random = Self {
+            state: 0,
+            increment: Self::DEFAULT_INCREMENT,
+        };
+
+        random.reseed(seed);
+        random
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.state = 0;
+        self.next_u32();
+        self.state = self.state.wrapping_add(seed);
+        self.next_u32();
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    // uniform over [min, max], bounds included
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let span = (max as i64 - min as i64 + 1) as u64;
+
+        // reject the values of the last incomplete span, so that modulo does not favor low values
+        let limit = (1u64 << 32) - (1u64 << 32) % span;
+
+        loop {
+            let value = self.next_u32() as u64;
+            if value < limit {
+                return (min as i64 + (value % span) as i64) as i32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(random: &mut Random) -> Vec<u32> {
+        (0..16).map(|_| random.next_u32()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        assert_eq!(sequence(&mut Random::new(42)), sequence(&mut Random::new(42)));
+        assert_ne!(sequence(&mut Random::new(42)), sequence(&mut Random::new(43)));
+    }
+
+    #[test]
+    fn reseed_restarts() {
+        let mut random = Random::new(7);
+        let first = sequence(&mut random);
+
+        random.reseed(7);
+        assert_eq!(sequence(&mut random), first);
+
+        random.reseed(8);
+        assert_eq!(sequence(&mut random), sequence(&mut Random::new(8)));
+    }
+
+    #[test]
+    fn range_bounds() {
+        let mut random = Random::new(1);
+
+        for _ in 0..100 {
+            assert_eq!(random.range(5, 5), 5);
+            assert_eq!(random.range(i32::MIN, i32::MIN), i32::MIN);
+            assert_eq!(random.range(i32::MAX, i32::MAX), i32::MAX);
+        }
+
+        // every value is drawn, none outside, whatever the order of the bounds
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            let value = random.range(3, -1);
+            assert!((-1..=3).contains(&value));
+            seen[(value + 1) as usize] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+
+        // the full span cannot overflow, and reaches both halves
+        let values: Vec<i32> = (0..1000).map(|_| random.range(i32::MIN, i32::MAX)).collect();
+        assert!(values.iter().any(|value| *value < 0));
+        assert!(values.iter().any(|value| *value > 0));
+
+        for _ in 0..1000 {
+            assert_ne!(random.range(i32::MIN + 1, i32::MAX), i32::MIN);
+            assert_ne!(random.range(i32::MIN, i32::MAX - 1), i32::MAX);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+pub struct Task {
+    stack: Box<[i32]>,
+    stack_index: usize,
+    instruction_index: usize,
+    // deadline, relative to the program start
+    wakeup_time: Duration,
+    waiting_frame: bool,
+    exited: bool,
+    entry_point: Option<usize>,
+}
+
+impl Task {
+    // when a deadline is missed by more than this (eg: the browser tab was in background), stop catching up
+    const MAX_LAG: Duration = Duration::from_millis(250);
+
+    pub fn new(stack_size: usize, instruction_index: usize, entry_point: Option<usize>, now: Duration) -> Self {
+        Self {
+            stack: vec![0; stack_size].into_boxed_slice(),
+            stack_index: 0,
+            instruction_index,
+            wakeup_time: now,
+            waiting_frame: false,
+            exited: false,
+            entry_point,
+        }
+    }
+
+    pub fn push(&mut self, value: i32) -> Result<()> {
+        if self.stack_index == self.stack.len() {
+            anyhow::bail!("Stack overflow");
+        }
+
+        self.stack[self.stack_index] = value;
+        self.stack_index += 1;
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<i32> {
+        if self.stack_index == 0 {
+            anyhow::bail!("Stack underflow");
+        }
+
+        self.stack_index -= 1;
+        Ok(self.stack[self.stack_index])
+    }
+
+    pub fn stack(&self) -> &[i32] {
+        &self.stack[..self.stack_index]
+    }
+
+    pub fn instruction_index(&self) -> usize {
+        self.instruction_index
+    }
+
+    pub fn set_instruction_index(&mut self, index: usize) {
+        self.instruction_index = index;
+    }
+
+    pub fn sleep(&mut self, duration: Duration, now: Duration) {
+        // schedule relative to the previous deadline, so that the latency of the frame that noticed the wakeup does not add up
+        let deadline = self.wakeup_time + duration;
+
+        self.wakeup_time = if deadline + Self::MAX_LAG < now {
+            now + duration
+        } else {
+            deadline
+        };
+    }
+
+    pub fn wait_frame(&mut self) {
+        self.waiting_frame = true;
+    }
+
+    pub fn end_wait_frame(&mut self, now: Duration) {
+        if self.waiting_frame {
+            self.waiting_frame = false;
+            self.wakeup_time = now;
+        }
+    }
+
+    pub fn sleeping(&self, now: Duration) -> bool {
+        self.waiting_frame || self.wakeup_time > now
+    }
+
+    pub fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn entry_point(&self) -> Option<usize> {
+        self.entry_point
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{clock::Clock, ExternalApi, VM};
+
+// Everything that comes from outside the VM, in the order the VM read it
+// input changes and parameter sets are recorded before the tick they apply to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum TraceEvent {
+    Tick,
+    InputChanged { channel: u32 },
+    SetParameter { name: String, value: i32 },
+    Clock { micros: u64 },
+    Len { value: usize },
+    Position { x: i32, y: i32 },
+    Width { value: i32 },
+    Height { value: i32 },
+    MatrixIndex { index: Option<usize> },
+    Get { color: (u8, u8, u8) },
+    Input { value: i32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    pub executable: String,
+    pub seed: u64,
+    // lights the program could draw on, a replay needs the same count
+    pub len: usize,
+    pub events: Vec<TraceEvent>,
+}
+
+#[derive(Clone)]
+pub struct TraceRecorder {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self {
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // must be called before each VM tick
+    pub fn tick(&self) {
+        self.record(TraceEvent::Tick);
+    }
+
+    // must be called when the VM is told that an input changed
+    pub fn input_changed(&self, channel: u32) {
+        self.record(TraceEvent::InputChanged { channel });
+    }
+
+    // must be called when a parameter of the VM is set
+    pub fn set_parameter(&self, name: &str, value: i32) {
+        self.record(TraceEvent::SetParameter {
+            name: name.to_string(),
+            value,
+        });
+    }
+
+    pub fn finish(&self, executable: String, seed: u64, len: usize) -> Trace {
+        Trace {
+            executable,
+            seed,
+            len,
+            events: std::mem::take(&mut *self.events.lock().unwrap()),
+        }
+    }
+
+    fn record(&self, event: TraceEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+pub struct RecordingApi {
+    inner: Box<dyn ExternalApi>,
+    recorder: TraceRecorder,
+}
+
+impl RecordingApi {
+    pub fn new(inner: Box<dyn ExternalApi>, recorder: TraceRecorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl ExternalApi for RecordingApi {
+    fn len(&self) -> usize {
+        let value = self.inner.len();
+        self.recorder.record(TraceEvent::Len { value });
+        value
+    }
+
+    fn position(&self, index: usize) -> (i32, i32) {
+        let (x, y) = self.inner.position(index);
+        self.recorder.record(TraceEvent::Position { x, y });
+        (x, y)
+    }
+
+    fn width(&self) -> i32 {
+        let value = self.inner.width();
+        self.recorder.record(TraceEvent::Width { value });
+        value
+    }
+
+    fn height(&self) -> i32 {
+        let value = self.inner.height();
+        self.recorder.record(TraceEvent::Height { value });
+        value
+    }
+
+    fn matrix_index(&self, x: usize, y: usize) -> Option<usize> {
+        let index = self.inner.matrix_index(x, y);
+        self.recorder.record(TraceEvent::MatrixIndex { index });
+        index
+    }
+
+    fn get(&self, index: usize) -> (u8, u8, u8) {
+        let color = self.inner.get(index);
+        self.recorder.record(TraceEvent::Get { color });
+        color
+    }
+
+    fn set(&self, index: usize, color: (u8, u8, u8)) {
+        self.inner.set(index, color);
+    }
+
+    fn fade_all(&self, amount: u8) {
+        self.inner.fade_all(amount);
+    }
+
+    fn fill(&self, start: usize, end: usize, color: (u8, u8, u8)) {
+        self.inner.fill(start, end, color);
+    }
+
+    fn gradient(&self, start: usize, end: usize, color1: (u8, u8, u8), color2: (u8, u8, u8)) {
+        self.inner.gradient(start, end, color1, color2);
+    }
+
+    fn shift(&self, offset: isize) {
+        self.inner.shift(offset);
+    }
+
+    fn rotate(&self, offset: isize) {
+        self.inner.rotate(offset);
+    }
+
+    fn mirror(&self, start: usize, end: usize) {
+        self.inner.mirror(start, end);
+    }
+
+    fn input(&self, channel: u32) -> i32 {
+        let value = self.inner.input(channel);
+        self.recorder.record(TraceEvent::Input { value });
+        value
+    }
+}
+
+pub struct RecordingClock {
+    inner: Box<dyn Clock>,
+    recorder: TraceRecorder,
+}
+
+impl RecordingClock {
+    pub fn new(inner: Box<dyn Clock>, recorder: TraceRecorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl Clock for RecordingClock {
+    fn now(&self) -> Duration {
+        let now = self.inner.now();
+        self.recorder.record(TraceEvent::Clock {
+            micros: now.as_micros() as u64,
+        });
+        now
+    }
+}
+
+#[derive(Clone)]
+pub struct TraceReplayer {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+struct ReplayState {
+    events: VecDeque<TraceEvent>,
+    last_clock: Duration,
+}
+
+impl TraceReplayer {
+    pub fn new(trace: Trace) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ReplayState {
+                events: trace.events.into(),
+                last_clock: Duration::ZERO,
+            })),
+        }
+    }
+
+    // must be called before each VM tick, gives the VM the input changes and parameter sets recorded for it
+    // returns false when the trace is over
+    pub fn tick(&self, vm: &mut VM) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            match state.events.pop_front() {
+                Some(TraceEvent::Tick) => return true,
+                Some(TraceEvent::InputChanged { channel }) => vm.input_changed(channel),
+                Some(TraceEvent::SetParameter { name, value }) => {
+                    if let Err(e) = vm.set_parameter(&name, value) {
+                        warn!("Replay: could not set parameter {}: {}", name, e);
+                    }
+                }
+                Some(event) => error!("Replay diverged: unused event {:?}", event),
+                None => return false,
+            }
+        }
+    }
+
+    fn next<T>(&self, extract: impl FnOnce(&TraceEvent) -> Option<T>) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+
+        // never consume past the end of the current tick
+        let value = state.events.front().and_then(extract);
+        if value.is_some() {
+            state.events.pop_front();
+        } else {
+            error!("Replay diverged: expected event not found, next is {:?}", state.events.front());
+        }
+
+        value
+    }
+}
+
+// Gives recorded values to the VM, outputs still go to the inner api
+pub struct ReplayApi {
+    inner: Box<dyn ExternalApi>,
+    replayer: TraceReplayer,
+}
+
+impl ReplayApi {
+    pub fn new(inner: Box<dyn ExternalApi>, replayer: TraceReplayer) -> Self {
+        Self { inner, replayer }
+    }
+}
+
+impl ExternalApi for ReplayApi {
+    fn len(&self) -> usize {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Len { value } => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.len())
+    }
+
+    fn position(&self, index: usize) -> (i32, i32) {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Position { x, y } => Some((*x, *y)),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.position(index))
+    }
+
+    fn width(&self) -> i32 {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Width { value } => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.width())
+    }
+
+    fn height(&self) -> i32 {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Height { value } => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.height())
+    }
+
+    fn matrix_index(&self, x: usize, y: usize) -> Option<usize> {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::MatrixIndex { index } => Some(*index),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.matrix_index(x, y))
+    }
+
+    fn get(&self, index: usize) -> (u8, u8, u8) {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Get { color } => Some(*color),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.get(index))
+    }
+
+    fn set(&self, index: usize, color: (u8, u8, u8)) {
+        self.inner.set(index, color);
+    }
+
+    fn fade_all(&self, amount: u8) {
+        self.inner.fade_all(amount);
+    }
+
+    fn fill(&self, start: usize, end: usize, color: (u8, u8, u8)) {
+        self.inner.fill(start, end, color);
+    }
+
+    fn gradient(&self, start: usize, end: usize, color1: (u8, u8, u8), color2: (u8, u8, u8)) {
+        self.inner.gradient(start, end, color1, color2);
+    }
+
+    fn shift(&self, offset: isize) {
+        self.inner.shift(offset);
+    }
+
+    fn rotate(&self, offset: isize) {
+        self.inner.rotate(offset);
+    }
+
+    fn mirror(&self, start: usize, end: usize) {
+        self.inner.mirror(start, end);
+    }
+
+    fn input(&self, channel: u32) -> i32 {
+        self.replayer
+            .next(|event| match event {
+                TraceEvent::Input { value } => Some(*value),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.inner.input(channel))
+    }
+}
+
+pub struct ReplayClock {
+    replayer: TraceReplayer,
+}
+
+impl ReplayClock {
+    pub fn new(replayer: TraceReplayer) -> Self {
+        Self { replayer }
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> Duration {
+        let now = self.replayer.next(|event| match event {
+            TraceEvent::Clock { micros } => Some(Duration::from_micros(*micros)),
+            _ => None,
+        });
+
+        let mut state = self.replayer.state.lock().unwrap();
+
+        // if the replay diverged, time stands still
+        if let Some(now) = now {
+            state.last_clock = now;
+        }
+
+        state.last_clock
+    }
+}
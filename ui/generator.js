@@ -27,6 +27,11 @@ class AstGenerator extends Blockly.CodeGenerator {
   }
 
   scrub_(block, code, thisOnly = false) {
+    if (!block.outputConnection) {
+      // Keep track of statement blocks for the debugger
+      code = JSON.stringify({ type: 'block', id: block.id, body: JSON.parse(code) });
+    }
+
     const nextBlock = block.nextConnection && block.nextConnection.targetBlock();
     if (!nextBlock || thisOnly) {
       return code;